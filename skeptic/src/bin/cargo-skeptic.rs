@@ -0,0 +1,354 @@
+//! `cargo skeptic`: checks the Rust examples of markdown files directly,
+//! without wiring up a build script.
+//!
+//! The examples link against the dependencies cargo has already built for
+//! the crate in the current directory, so run `cargo build`, or `cargo test
+//! --no-run` for dev-dependencies, first.
+
+use std::env;
+use std::path::PathBuf;
+use std::process::{self, Command};
+
+use skeptic::{Example, Skeptic};
+
+const USAGE: &str = "\
+Checks the Rust examples in markdown files
+
+Usage: cargo skeptic [OPTIONS] [FILES]...
+
+Files default to README.md, unless a book is given.
+
+Options:
+    --manifest-path PATH  Crate whose built dependencies the examples use
+    --target TRIPLE       Target the dependencies were built for
+    --release             Use dependencies built in release mode
+    --implicit-rust       Test unlabeled code blocks as Rust, like rustdoc
+    --wrap-main           Wrap examples in `fn main`, like rustdoc
+    --book DIR            Also check the chapters of the mdBook in DIR
+    --filter PATTERN      Only check examples whose path contains PATTERN
+    --exact               Match filters against whole paths
+    --include-ignored     Also check examples marked `ignore`
+    --list                List the examples instead of checking them
+    -h, --help            Print this message";
+
+struct Args {
+    files: Vec<PathBuf>,
+    books: Vec<PathBuf>,
+    manifest_path: Option<PathBuf>,
+    target: Option<String>,
+    release: bool,
+    implicit_rust: bool,
+    wrap_main: bool,
+    filters: Vec<String>,
+    exact: bool,
+    include_ignored: bool,
+    list: bool,
+}
+
+fn main() {
+    let args = match parse_args(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(msg) => {
+            eprintln!("error: {}\n\n{}", msg, USAGE);
+            process::exit(2);
+        }
+    };
+
+    let examples = match examples(&args) {
+        Ok(examples) => examples,
+        Err(msg) => {
+            eprintln!("error: {}", msg);
+            process::exit(2);
+        }
+    };
+    let total = examples.len();
+    let examples = examples
+        .into_iter()
+        .filter(|example| matches_filters(&args, example.path()))
+        .collect::<Vec<_>>();
+    let filtered_out = total - examples.len();
+
+    if args.list {
+        for example in &examples {
+            println!("{}", example.path());
+        }
+        return;
+    }
+
+    println!("\nrunning {} examples", examples.len());
+    let mut passed = 0;
+    let mut ignored = 0;
+    let mut failures = Vec::new();
+    for example in &examples {
+        if example.is_ignored() && !args.include_ignored {
+            println!("example {} ... ignored", example.path());
+            ignored += 1;
+            continue;
+        }
+        match example.check() {
+            Ok(()) => {
+                println!("example {} ... ok", example.path());
+                passed += 1;
+            }
+            Err(err) => {
+                println!("example {} ... FAILED", example.path());
+                failures.push((example.path(), err));
+            }
+        }
+    }
+
+    if !failures.is_empty() {
+        println!("\nfailures:");
+        for &(name, ref err) in &failures {
+            println!("\n---- {} ----\n{}", name, err);
+        }
+    }
+    println!(
+        "\nresult: {}. {} passed; {} failed; {} ignored; {} filtered out\n",
+        if failures.is_empty() { "ok" } else { "FAILED" },
+        passed,
+        failures.len(),
+        ignored,
+        filtered_out
+    );
+    if !failures.is_empty() {
+        process::exit(101);
+    }
+}
+
+fn parse_args<I: Iterator<Item = String>>(args: I) -> Result<Args, String> {
+    let mut parsed = Args {
+        files: Vec::new(),
+        books: Vec::new(),
+        manifest_path: None,
+        target: None,
+        release: false,
+        implicit_rust: false,
+        wrap_main: false,
+        filters: Vec::new(),
+        exact: false,
+        include_ignored: false,
+        list: false,
+    };
+
+    // Cargo passes the name of the subcommand along
+    let mut args = args.peekable();
+    if args.peek().map(String::as_str) == Some("skeptic") {
+        args.next();
+    }
+
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .ok_or_else(|| format!("{} requires a value", name))
+        };
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            "--manifest-path" => parsed.manifest_path = Some(PathBuf::from(value(&arg)?)),
+            "--target" => parsed.target = Some(value(&arg)?),
+            "--release" => parsed.release = true,
+            "--implicit-rust" => parsed.implicit_rust = true,
+            "--wrap-main" => parsed.wrap_main = true,
+            "--book" => parsed.books.push(PathBuf::from(value(&arg)?)),
+            "--filter" => parsed.filters.push(value(&arg)?),
+            "--exact" => parsed.exact = true,
+            "--include-ignored" => parsed.include_ignored = true,
+            "--list" => parsed.list = true,
+            _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+            _ => parsed.files.push(PathBuf::from(arg)),
+        }
+    }
+
+    if parsed.files.is_empty() && parsed.books.is_empty() {
+        parsed.files.push(PathBuf::from("README.md"));
+    }
+    Ok(parsed)
+}
+
+fn examples(args: &Args) -> Result<Vec<Example>, String> {
+    let mut metadata = cargo_metadata::MetadataCommand::new();
+    metadata.no_deps();
+    if let Some(ref path) = args.manifest_path {
+        metadata.manifest_path(path);
+    }
+    let metadata = metadata.exec().map_err(|err| err.to_string())?;
+
+    // Cargo looks for the manifest upwards from the current directory, so the
+    // crate's root is wherever it found it rather than the current directory
+    let root_dir = match metadata.root_package() {
+        Some(package) => package
+            .manifest_path
+            .parent()
+            .map(|dir| dir.as_std_path().to_path_buf())
+            .ok_or_else(|| format!("invalid manifest path {}", package.manifest_path))?,
+        None => metadata.workspace_root.as_std_path().to_path_buf(),
+    };
+
+    let mut target_dir = PathBuf::from(metadata.target_directory);
+    let target = match args.target {
+        Some(ref target) => {
+            target_dir.push(target);
+            target.clone()
+        }
+        None => host_triple()?,
+    };
+    target_dir.push(if args.release { "release" } else { "debug" });
+
+    // Files are given relative to the current directory rather than to the
+    // crate
+    let current_dir = env::current_dir().map_err(|err| err.to_string())?;
+    let mut skeptic = Skeptic::new();
+    for file in &args.files {
+        let file = current_dir.join(file);
+        if args.implicit_rust {
+            skeptic.implicit_rust_doc(file);
+        } else {
+            skeptic.doc(file);
+        }
+    }
+    for book in &args.books {
+        skeptic.book(current_dir.join(book));
+    }
+
+    skeptic
+        .root_dir(&root_dir)
+        .out_dir(target_dir.join("skeptic"))
+        .target_dir(&target_dir)
+        .target(target)
+        .wrap_main(args.wrap_main)
+        .examples()
+        .map_err(|err| err.to_string())
+}
+
+fn host_triple() -> Result<String, String> {
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| String::from("rustc"));
+    let output = Command::new(rustc)
+        .arg("-vV")
+        .output()
+        .map_err(|err| format!("failed to run rustc: {}", err))?;
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| line.strip_prefix("host: "))
+        .map(str::to_owned)
+        .ok_or_else(|| String::from("failed to determine the host target"))
+}
+
+fn matches_filters(args: &Args, name: &str) -> bool {
+    args.filters.is_empty()
+        || args.filters.iter().any(|filter| {
+            if args.exact {
+                name == filter
+            } else {
+                name.contains(filter.as_str())
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{matches_filters, parse_args, Args};
+    use std::path::PathBuf;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn subcommand_name_is_stripped() {
+        let args = parse(&["skeptic", "--list"]).unwrap();
+        assert!(args.list);
+        assert_eq!(args.files, vec![PathBuf::from("README.md")]);
+    }
+
+    #[test]
+    fn files_default_to_readme_unless_a_book_is_given() {
+        let args = parse(&[]).unwrap();
+        assert_eq!(args.files, vec![PathBuf::from("README.md")]);
+
+        let args = parse(&["--book", "guide"]).unwrap();
+        assert!(args.files.is_empty());
+        assert_eq!(args.books, vec![PathBuf::from("guide")]);
+
+        let args = parse(&["docs/a.md", "docs/b.md"]).unwrap();
+        assert_eq!(
+            args.files,
+            vec![PathBuf::from("docs/a.md"), PathBuf::from("docs/b.md")]
+        );
+    }
+
+    #[test]
+    fn options_are_parsed() {
+        let args = parse(&[
+            "--manifest-path",
+            "sub/Cargo.toml",
+            "--target",
+            "x86_64-unknown-linux-gnu",
+            "--release",
+            "--implicit-rust",
+            "--wrap-main",
+            "--filter",
+            "README",
+            "--exact",
+            "--include-ignored",
+        ])
+        .unwrap();
+        assert_eq!(args.manifest_path, Some(PathBuf::from("sub/Cargo.toml")));
+        assert_eq!(args.target.as_deref(), Some("x86_64-unknown-linux-gnu"));
+        assert!(args.release);
+        assert!(args.implicit_rust);
+        assert!(args.wrap_main);
+        assert_eq!(args.filters, vec![String::from("README")]);
+        assert!(args.exact);
+        assert!(args.include_ignored);
+        assert!(!args.list);
+    }
+
+    #[test]
+    fn missing_values_are_rejected() {
+        for option in &["--manifest-path", "--target", "--book", "--filter"] {
+            let err = parse(&[option]).err().unwrap();
+            assert_eq!(err, format!("{} requires a value", option));
+        }
+    }
+
+    #[test]
+    fn unknown_options_are_rejected() {
+        let err = parse(&["--frobnicate"]).err().unwrap();
+        assert_eq!(err, "unknown option --frobnicate");
+    }
+
+    const FIRST: &str =
+        "readme::be_a_rust_documentation_skeptic::getting_started::readme_sect_getting_started_line_33";
+    const SECOND: &str =
+        "readme::be_a_rust_documentation_skeptic::getting_started::readme_sect_getting_started_line_330";
+    const OTHER: &str = "guide::installation::guide_sect_installation_line_12";
+
+    #[test]
+    fn filters_match_substrings() {
+        let args = parse(&["--filter", "getting_started"]).unwrap();
+        assert!(matches_filters(&args, FIRST));
+        assert!(matches_filters(&args, SECOND));
+        assert!(!matches_filters(&args, OTHER));
+
+        let args = parse(&["--filter", "readme::", "--filter", "installation"]).unwrap();
+        assert!(matches_filters(&args, FIRST));
+        assert!(matches_filters(&args, OTHER));
+
+        let args = parse(&[]).unwrap();
+        assert!(matches_filters(&args, OTHER));
+    }
+
+    #[test]
+    fn exact_filters_match_whole_paths() {
+        let args = parse(&["--filter", "readme_sect_getting_started_line_33", "--exact"]).unwrap();
+        assert!(!matches_filters(&args, FIRST));
+
+        let args = parse(&["--filter", FIRST, "--exact"]).unwrap();
+        assert!(matches_filters(&args, FIRST));
+        assert!(!matches_filters(&args, SECOND));
+        assert!(!matches_filters(&args, OTHER));
+    }
+}
@@ -0,0 +1,120 @@
+use std::path::{Path, PathBuf};
+
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
+use ignore::WalkBuilder;
+
+use crate::Error;
+
+/// Finds markdown files under a directory.
+///
+/// Unlike [`markdown_files_of_directory`](crate::markdown_files_of_directory),
+/// this skips files ignored by `.gitignore` and can be narrowed down with
+/// glob patterns, so it can safely be pointed at the root of a large
+/// repository.
+///
+/// Patterns are matched against paths relative to the directory, with `/`
+/// as the separator. `*` doesn't cross directories, `**` does. Without any
+/// include pattern, every `.md` file is included.
+///
+/// # Usage
+///
+/// ```rust,no_run
+/// extern crate skeptic;
+///
+/// use skeptic::{MarkdownFiles, Skeptic};
+///
+/// fn main() {
+///     let docs = MarkdownFiles::new(".")
+///         .include("**/*.md")
+///         .exclude("vendor/**")
+///         .exclude("CHANGELOG.md")
+///         .find()
+///         .unwrap();
+///     Skeptic::new().docs(&docs).generate();
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct MarkdownFiles {
+    dir: PathBuf,
+    include: Vec<String>,
+    exclude: Vec<String>,
+    gitignore: bool,
+}
+
+impl MarkdownFiles {
+    /// Searches `dir` for markdown files.
+    pub fn new<P: AsRef<Path>>(dir: P) -> MarkdownFiles {
+        MarkdownFiles {
+            dir: dir.as_ref().to_owned(),
+            include: Vec::new(),
+            exclude: Vec::new(),
+            gitignore: true,
+        }
+    }
+
+    /// Only returns files matching one of the include patterns.
+    pub fn include(&mut self, pattern: &str) -> &mut MarkdownFiles {
+        self.include.push(pattern.to_owned());
+        self
+    }
+
+    /// Leaves out files matching the pattern, even if they are included.
+    pub fn exclude(&mut self, pattern: &str) -> &mut MarkdownFiles {
+        self.exclude.push(pattern.to_owned());
+        self
+    }
+
+    /// Whether to skip files ignored by `.gitignore` and similar files.
+    ///
+    /// Enabled by default.
+    pub fn gitignore(&mut self, gitignore: bool) -> &mut MarkdownFiles {
+        self.gitignore = gitignore;
+        self
+    }
+
+    /// Returns the matching files, sorted by path.
+    pub fn find(&self) -> Result<Vec<PathBuf>, Error> {
+        let include = if self.include.is_empty() {
+            glob_set(&[String::from("**/*.md")])?
+        } else {
+            glob_set(&self.include)?
+        };
+        let exclude = glob_set(&self.exclude)?;
+
+        let mut walker = WalkBuilder::new(&self.dir);
+        walker
+            .standard_filters(self.gitignore)
+            .hidden(false)
+            .require_git(false)
+            .filter_entry(|entry| entry.file_name() != ".git");
+
+        let mut files = Vec::new();
+        for entry in walker.build() {
+            let entry = entry.map_err(|err| Error::Discovery(err.to_string()))?;
+            if !entry.file_type().is_some_and(|kind| kind.is_file()) {
+                continue;
+            }
+            let relative = entry.path().strip_prefix(&self.dir).unwrap_or(entry.path());
+            if include.is_match(relative) && !exclude.is_match(relative) {
+                files.push(entry.into_path());
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
+}
+
+fn glob_set(patterns: &[String]) -> Result<GlobSet, Error> {
+    let mut set = GlobSetBuilder::new();
+    for pattern in patterns {
+        set.add(glob(pattern)?);
+    }
+    set.build().map_err(|err| Error::Discovery(err.to_string()))
+}
+
+fn glob(pattern: &str) -> Result<Glob, Error> {
+    GlobBuilder::new(pattern)
+        .literal_separator(true)
+        .build()
+        .map_err(|err| Error::Discovery(err.to_string()))
+}
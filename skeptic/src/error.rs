@@ -0,0 +1,197 @@
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// Errors that can occur while generating or running tests.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// Reading or writing a file failed.
+    Io(io::Error),
+    /// Running `cargo metadata` failed.
+    Metadata(cargo_metadata::Error),
+    /// A variable that cargo sets for build scripts is missing.
+    MissingEnv(&'static str),
+    /// Code blocks refer to templates that aren't defined.
+    MissingTemplates(Vec<MissingTemplate>),
+    /// Test names, given in info strings or by a naming scheme, aren't
+    /// valid Rust identifiers.
+    InvalidTestNames(Vec<InvalidTestName>),
+    /// Several code blocks would generate tests with the same name in the
+    /// same module.
+    DuplicateTestNames(Vec<DuplicateTestName>),
+    /// A code block's info string gives a malformed value, such as a
+    /// `timeout` that isn't a number of seconds.
+    InfoString(String),
+    /// A template uses an unknown placeholder, or lacks `{{snippet}}`.
+    Template(String),
+    /// An mdBook's configuration, summary or included files could not be
+    /// read.
+    Book(String),
+    /// Searching for markdown files failed, or a glob pattern is invalid.
+    Discovery(String),
+    /// The compiled dependencies of the crate could not be located.
+    Fingerprint(String),
+    /// An example did not compile as expected.
+    Compile(String),
+    /// An example did not run as expected.
+    Run(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref err) => write!(f, "I/O error: {}", err),
+            Error::Metadata(ref err) => write!(f, "failed to read cargo metadata: {}", err),
+            Error::MissingEnv(name) => write!(
+                f,
+                "environment variable {} is not set, is this running from a build script?",
+                name
+            ),
+            Error::MissingTemplates(ref missing) => {
+                f.write_str("templates not found:")?;
+                for template in missing {
+                    write!(f, "\n  {}", template)?;
+                }
+                Ok(())
+            }
+            Error::InvalidTestNames(ref invalid) => {
+                f.write_str("invalid test names:")?;
+                for name in invalid {
+                    write!(f, "\n  {}", name)?;
+                }
+                Ok(())
+            }
+            Error::DuplicateTestNames(ref duplicates) => {
+                f.write_str("duplicate test names:")?;
+                for name in duplicates {
+                    write!(f, "\n  {}", name)?;
+                }
+                Ok(())
+            }
+            Error::InfoString(ref msg) => write!(f, "invalid info string: {}", msg),
+            Error::Template(ref msg) => write!(f, "invalid template: {}", msg),
+            Error::Book(ref msg) => write!(f, "failed to read book: {}", msg),
+            Error::Discovery(ref msg) => write!(f, "failed to find markdown files: {}", msg),
+            Error::Fingerprint(ref msg) => write!(f, "failed to locate dependencies: {}", msg),
+            Error::Compile(ref msg) | Error::Run(ref msg) => f.write_str(msg),
+        }
+    }
+}
+
+/// A code block that refers to a template that isn't defined.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct MissingTemplate {
+    /// Name of the template, without the `skt-` prefix.
+    pub template: String,
+    /// The document containing the code block.
+    pub path: PathBuf,
+    /// Line of the code block's opening fence.
+    pub line: usize,
+    /// The file templates for the document are read from.
+    pub templates_file: PathBuf,
+    /// The files of the template library shared by every document.
+    pub library: Vec<PathBuf>,
+    /// Names of the templates these files define, without the `skt-`
+    /// prefix.
+    pub available: Vec<String>,
+}
+
+impl fmt::Display for MissingTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}: skt-{} is not defined",
+            self.path.display(),
+            self.line,
+            self.template
+        )?;
+        let mut files = vec![self.templates_file.display().to_string()];
+        files.extend(self.library.iter().map(|file| file.display().to_string()));
+        let (files, verb) = match files.split_last() {
+            Some((last, rest)) if !rest.is_empty() => {
+                (format!("{} and {}", rest.join(", "), last), "define")
+            }
+            _ => (files.join(""), "defines"),
+        };
+        if self.available.is_empty() {
+            write!(f, ", {} {} no templates", files, verb)
+        } else {
+            let names = self
+                .available
+                .iter()
+                .map(|name| format!("skt-{}", name))
+                .collect::<Vec<_>>();
+            write!(f, ", {} {} {}", files, verb, names.join(", "))
+        }
+    }
+}
+
+/// A code block whose test name isn't a valid Rust identifier.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct InvalidTestName {
+    /// The name, after replacing unsupported characters with `_`.
+    pub name: String,
+    /// The document containing the code block.
+    pub path: PathBuf,
+    /// Line of the code block's opening fence.
+    pub line: usize,
+}
+
+impl fmt::Display for InvalidTestName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}: `{}` is not a valid test name",
+            self.path.display(),
+            self.line,
+            self.name
+        )
+    }
+}
+
+/// A test name shared by several code blocks.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct DuplicateTestName {
+    /// The name.
+    pub name: String,
+    /// The document and opening fence line of each code block using it.
+    pub locations: Vec<(PathBuf, usize)>,
+}
+
+impl fmt::Display for DuplicateTestName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let locations = self
+            .locations
+            .iter()
+            .map(|&(ref path, line)| format!("{}:{}", path.display(), line))
+            .collect::<Vec<_>>();
+        write!(f, "`{}` is used by {}", self.name, locations.join(", "))
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match *self {
+            Error::Io(ref err) => Some(err),
+            Error::Metadata(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+impl From<cargo_metadata::Error> for Error {
+    fn from(err: cargo_metadata::Error) -> Error {
+        Error::Metadata(err)
+    }
+}
@@ -1,18 +1,31 @@
 use std::collections::HashMap;
 use std::env;
+use std::fmt;
 use std::fs::File;
 use std::io::{self, Error as IoError, Read, Write};
 use std::mem;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 
 use pulldown_cmark::{CodeBlockKind, Event, HeadingLevel, Parser, Tag};
 
+pub use discovery::MarkdownFiles;
+pub use error::{DuplicateTestName, Error, InvalidTestName, MissingTemplate};
+
+mod discovery;
+mod error;
+mod mdbook;
 pub mod rt;
 #[cfg(test)]
 mod tests;
 
 /// Returns a list of markdown files under a directory.
 ///
+/// Every markdown file is returned, including those in build output or
+/// vendored dependencies. See [`MarkdownFiles`] to skip ignored files or
+/// filter them by pattern.
+///
 /// # Usage
 ///
 /// List markdown files of `mdbook` which are under `<project dir>/book` usually:
@@ -40,7 +53,7 @@ pub fn markdown_files_of_directory(dir: &str) -> Vec<PathBuf> {
         .expect("Failed to read glob pattern")
         .filter_map(Result::ok)
     {
-        out.push(path.to_str().unwrap().into());
+        out.push(path);
     }
 
     out
@@ -48,6 +61,9 @@ pub fn markdown_files_of_directory(dir: &str) -> Vec<PathBuf> {
 
 /// Generates tests for specified markdown files.
 ///
+/// This is a shortcut for [`Skeptic`] with every setting left at its
+/// default.
+///
 /// # Usage
 ///
 /// Generates doc tests for the specified files.
@@ -75,72 +91,658 @@ pub fn markdown_files_of_directory(dir: &str) -> Vec<PathBuf> {
 ///     generate_doc_tests(&mdbook_files);
 /// }
 /// ```
-pub fn generate_doc_tests<T: Clone>(docs: &[T])
+pub fn generate_doc_tests<T>(docs: &[T])
+where
+    T: AsRef<Path>,
+{
+    Skeptic::new().docs(docs).generate();
+}
+
+/// Generates tests for specified markdown files, returning an error
+/// instead of panicking if that fails.
+///
+/// # Usage
+///
+/// ```rust,no_run
+/// extern crate skeptic;
+///
+/// fn main() {
+///     if let Err(err) = skeptic::try_generate_doc_tests(&["README.md"]) {
+///         eprintln!("failed to generate tests from README.md: {}", err);
+///         std::process::exit(1);
+///     }
+/// }
+/// ```
+pub fn try_generate_doc_tests<T>(docs: &[T]) -> Result<(), Error>
 where
     T: AsRef<Path>,
 {
-    // This shortcut is specifically so examples in skeptic's on
-    // readme can call this function in non-build.rs contexts, without
-    // panicking below.
-    if docs.is_empty() {
-        return;
+    Skeptic::new().docs(docs).try_generate()
+}
+
+/// Builder for generating tests from markdown files.
+///
+/// Every setting that is not given explicitly falls back to the
+/// environment cargo provides to build scripts, so the output lands in
+/// `$OUT_DIR/skeptic-tests.rs` and documents are resolved relative to
+/// `$CARGO_MANIFEST_DIR`.
+///
+/// # Usage
+///
+/// ```rust,no_run
+/// extern crate skeptic;
+///
+/// use skeptic::Skeptic;
+///
+/// fn main() {
+///     Skeptic::new()
+///         .docs(&["README.md", "CONTRIBUTING.md"])
+///         .out_file("readme-tests.rs")
+///         .generate();
+/// }
+/// ```
+///
+/// The generated file is then included from the test crate under the
+/// chosen name:
+///
+/// ```rust,ignore
+/// include!(concat!(env!("OUT_DIR"), "/readme-tests.rs"));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Skeptic {
+    docs: Vec<Doc>,
+    books: Vec<PathBuf>,
+    out_dir: Option<PathBuf>,
+    out_file: Option<PathBuf>,
+    root_dir: Option<PathBuf>,
+    target_dir: Option<PathBuf>,
+    target_triple: Option<String>,
+    batch: bool,
+    naming: Option<Naming>,
+    timeout: Option<Duration>,
+    legacy_templates: bool,
+    templates: Vec<PathBuf>,
+    wrap_main: bool,
+}
+
+impl Skeptic {
+    /// Creates a builder with no documents and default settings.
+    pub fn new() -> Skeptic {
+        Skeptic::default()
     }
 
-    let docs = docs
-        .iter()
-        .cloned()
-        .map(|path| path.as_ref().to_str().unwrap().to_owned())
-        .filter(|d| !d.ends_with(".skt.md"))
-        .collect::<Vec<_>>();
+    /// Adds a markdown file to generate tests for.
+    ///
+    /// Relative paths are resolved against the root directory. Template
+    /// files ending in `.skt.md` are skipped.
+    pub fn doc<P: AsRef<Path>>(&mut self, doc: P) -> &mut Skeptic {
+        self.docs.push(Doc::new(doc));
+        self
+    }
+
+    /// Adds several markdown files to generate tests for.
+    pub fn docs<P: AsRef<Path>>(&mut self, docs: &[P]) -> &mut Skeptic {
+        self.docs.extend(docs.iter().map(Doc::new));
+        self
+    }
+
+    /// Adds a markdown file in which code blocks are Rust unless labeled
+    /// otherwise, like in rustdoc.
+    ///
+    /// Indented code blocks, and fenced ones whose info string is empty or
+    /// only holds words such as `ignore` or `no_run`, are then tested too.
+    /// This suits documents written for rustdoc, which don't label their
+    /// examples `rust`.
+    pub fn implicit_rust_doc<P: AsRef<Path>>(&mut self, doc: P) -> &mut Skeptic {
+        let mut doc = Doc::new(doc);
+        doc.implicit_rust = true;
+        self.docs.push(doc);
+        self
+    }
+
+    /// Adds the chapters of an mdBook.
+    ///
+    /// `dir` is the directory containing the book's `book.toml`, relative
+    /// to the root directory. The chapters linked from the book's
+    /// `SUMMARY.md` are tested, except for drafts, after expanding their
+    /// `{{#include}}` and `{{#rustdoc_include}}` directives.
+    pub fn book<P: AsRef<Path>>(&mut self, dir: P) -> &mut Skeptic {
+        self.books.push(dir.as_ref().to_owned());
+        self
+    }
+
+    /// Sets the directory the generated tests and their build artifacts
+    /// are written to.
+    ///
+    /// Defaults to `$OUT_DIR`. The directory is also used at test time to
+    /// locate the compiled dependencies of the crate, so it should stay
+    /// inside cargo's target directory.
+    pub fn out_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Skeptic {
+        self.out_dir = Some(dir.as_ref().to_owned());
+        self
+    }
+
+    /// Sets the file the generated tests are written to.
+    ///
+    /// Relative paths are resolved against the output directory. Defaults
+    /// to `skeptic-tests.rs`.
+    pub fn out_file<P: AsRef<Path>>(&mut self, file: P) -> &mut Skeptic {
+        self.out_file = Some(file.as_ref().to_owned());
+        self
+    }
 
-    // Inform cargo that it needs to rerun the build script if one of the skeptic files are
-    // modified
-    for doc in &docs {
-        println!("cargo:rerun-if-changed={}", doc);
+    /// Sets the directory relative document paths are resolved against.
+    ///
+    /// Defaults to `$CARGO_MANIFEST_DIR`. This must be the directory of the
+    /// crate whose dependencies the examples link against.
+    pub fn root_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Skeptic {
+        self.root_dir = Some(dir.as_ref().to_owned());
+        self
+    }
+
+    /// Sets the directory the crate and its dependencies were built in,
+    /// such as `target/debug`.
+    ///
+    /// Defaults to the directory three levels above the output directory,
+    /// which is where cargo builds the crate when the output directory is
+    /// `$OUT_DIR`.
+    pub fn target_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Skeptic {
+        self.target_dir = Some(dir.as_ref().to_owned());
+        self
+    }
+
+    /// Sets the target triple the examples are compiled for.
+    ///
+    /// Defaults to `$TARGET`.
+    pub fn target<S: Into<String>>(&mut self, triple: S) -> &mut Skeptic {
+        self.target_triple = Some(triple.into());
+        self
+    }
+
+    /// Compiles the examples of each document together where possible.
+    ///
+    /// Examples that are simply compiled and run are built as modules of a
+    /// single binary per document, instead of spawning rustc once per
+    /// example, which saves a lot of time on large documents. If that
+    /// binary fails to build, each example falls back to being compiled on
+    /// its own. Examples are compiled with the newest edition of the
+    /// workspace, so this is best suited for crates on the 2018 edition or
+    /// later.
+    pub fn batch(&mut self, batch: bool) -> &mut Skeptic {
+        self.batch = batch;
+        self
+    }
 
-        let skt = format!("{}.skt.md", doc);
-        if Path::new(&skt).exists() {
-            println!("cargo:rerun-if-changed={}", skt);
+    /// Reads templates in the `format!` syntax of skeptic 0.13, where `{}`
+    /// stands for the example and literal braces are doubled, instead of
+    /// with `{{snippet}}` placeholders.
+    ///
+    /// Without it, only templates that have no `{{snippet}}` placeholder,
+    /// but do have a single `{}`, are read this way. This eases upgrading
+    /// documents with many templates.
+    pub fn legacy_templates(&mut self, legacy: bool) -> &mut Skeptic {
+        self.legacy_templates = legacy;
+        self
+    }
+
+    /// Wraps examples in `fn main`, the way rustdoc does.
+    ///
+    /// Examples without a template that don't define `main` have their
+    /// code wrapped in one, leading `#![...]` crate attributes and
+    /// `extern crate` lines aside. An example ending with `Ok(())` gets a
+    /// `main` returning a `Result`, so it can use `?`. Examples that
+    /// mention the crate get an `extern crate` for it, unless they have
+    /// one.
+    pub fn wrap_main(&mut self, wrap: bool) -> &mut Skeptic {
+        self.wrap_main = wrap;
+        self
+    }
+
+    /// Adds templates shared by every document, read from a markdown file
+    /// or from the markdown files of a directory.
+    ///
+    /// Relative paths are resolved against the root directory. A template
+    /// defined in several files is taken from the last one, and the
+    /// `.skt.md` file of a document overrides them all. Defaults to
+    /// `skeptic-templates.md` in the root directory, if it exists.
+    pub fn templates<P: AsRef<Path>>(&mut self, path: P) -> &mut Skeptic {
+        self.templates.push(path.as_ref().to_owned());
+        self
+    }
+
+    /// Sets how long examples may run before they are killed and their
+    /// test fails.
+    ///
+    /// Defaults to [`rt::DEFAULT_TIMEOUT`]. A `timeout=SECONDS` attribute in
+    /// an example's info string takes precedence.
+    pub fn timeout(&mut self, timeout: Duration) -> &mut Skeptic {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Names the tests of code blocks without an explicit `name=` in their
+    /// info string.
+    ///
+    /// The names returned are lowercased and characters other than ASCII
+    /// letters and digits are replaced with `_`, like the default names.
+    /// Generating the tests fails if a name isn't a valid identifier, or if
+    /// several code blocks under the same heading of a document end up with
+    /// the same name.
+    ///
+    /// ```rust
+    /// extern crate skeptic;
+    ///
+    /// use skeptic::Skeptic;
+    ///
+    /// fn main() {
+    ///     Skeptic::new()
+    ///         .doc("README.md")
+    ///         .test_names(|parts| format!("{}_example_{}", parts.file_stem, parts.index + 1));
+    /// }
+    /// ```
+    pub fn test_names<F>(&mut self, naming: F) -> &mut Skeptic
+    where
+        F: Fn(&TestNameParts) -> String + Send + Sync + 'static,
+    {
+        self.naming = Some(Naming(Arc::new(naming)));
+        self
+    }
+
+    /// Generates the tests.
+    ///
+    /// # Panics
+    ///
+    /// Panics if generating the tests fails, see
+    /// [`try_generate`](Skeptic::try_generate).
+    pub fn generate(&self) {
+        if let Err(err) = self.try_generate() {
+            panic!("{}", err);
         }
     }
 
-    let out_dir = env::var("OUT_DIR").unwrap();
-    let cargo_manifest_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    /// Generates the tests, returning an error if that fails.
+    ///
+    /// This fails if a setting was left unset and cargo's corresponding
+    /// environment variable is missing, if a document refers to a template
+    /// that doesn't exist or is invalid, or if reading the documents or
+    /// writing the tests fails.
+    pub fn try_generate(&self) -> Result<(), Error> {
+        // This shortcut is specifically so examples in skeptic's on
+        // readme can call this function in non-build.rs contexts, without
+        // failing below.
+        if self.docs.is_empty() && self.books.is_empty() {
+            return Ok(());
+        }
 
-    let mut out_file = PathBuf::from(out_dir.clone());
-    out_file.push("skeptic-tests.rs");
+        let config = self.config()?;
+        for path in &config.watched {
+            println!("cargo:rerun-if-changed={}", path.display());
+        }
 
-    let config = Config {
-        out_dir: PathBuf::from(out_dir),
-        root_dir: PathBuf::from(cargo_manifest_dir),
-        out_file,
-        target_triple: env::var("TARGET").expect("could not get target triple"),
-        docs,
-    };
+        // Inform cargo that it needs to rerun the build script if one of the skeptic files are
+        // modified
+        for doc in &config.docs {
+            let path = config.root_dir.join(&doc.path);
+            println!("cargo:rerun-if-changed={}", path.display());
 
-    run(&config);
+            let skt = templates_file(&path);
+            if skt.exists() {
+                println!("cargo:rerun-if-changed={}", skt.display());
+            }
+        }
+
+        run(&config)
+    }
+
+    /// Extracts the examples of the documents, to check them directly
+    /// instead of through generated tests.
+    ///
+    /// The same settings apply as for [`generate`](Skeptic::generate),
+    /// though nothing is written to the output file.
+    pub fn examples(&self) -> Result<Vec<Example>, Error> {
+        if self.docs.is_empty() && self.books.is_empty() {
+            return Ok(Vec::new());
+        }
+        let config = self.config()?;
+        let suite = extract_tests(&config)?;
+
+        let mut examples = Vec::new();
+        for doc_test in &suite.doc_tests {
+            let source_path = doc_test
+                .path
+                .strip_prefix(&config.root_dir)
+                .unwrap_or(&doc_test.path);
+            for test in &doc_test.tests {
+                let wrapper = test_wrapper(&config, doc_test, test, source_path)?;
+                let module = test_module(doc_test, test);
+                examples.push(create_example(
+                    &config,
+                    &wrapper,
+                    test,
+                    &module,
+                    source_path,
+                ));
+            }
+        }
+        Ok(examples)
+    }
+
+    fn config(&self) -> Result<Config, Error> {
+        let mut docs = self
+            .docs
+            .iter()
+            .filter(|doc| !doc.path.to_string_lossy().ends_with(".skt.md"))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let out_dir = match self.out_dir {
+            Some(ref dir) => dir.clone(),
+            None => PathBuf::from(cargo_env("OUT_DIR")?),
+        };
+        let root_dir = match self.root_dir {
+            Some(ref dir) => dir.clone(),
+            None => PathBuf::from(cargo_env("CARGO_MANIFEST_DIR")?),
+        };
+        let out_file = out_dir.join(
+            self.out_file
+                .as_ref()
+                .map_or(Path::new("skeptic-tests.rs"), PathBuf::as_path),
+        );
+        let target_triple = match self.target_triple {
+            Some(ref triple) => triple.clone(),
+            None => cargo_env("TARGET")?,
+        };
+
+        let mut watched = Vec::new();
+        for book in &self.books {
+            let book_dir = root_dir.join(book);
+            let src_dir = mdbook::source_dir(&book_dir)?;
+            let book_module = book_dir
+                .file_name()
+                .map(|name| sanitize_test_name(&name.to_string_lossy()));
+            for chapter in mdbook::chapters(&src_dir)? {
+                let mut doc = Doc::new(&chapter);
+                doc.expand_includes = true;
+                // Chapters are grouped under the book, following the
+                // layout of its source directory
+                let relative = chapter.strip_prefix(&src_dir).unwrap_or(&chapter);
+                doc.module = book_module
+                    .iter()
+                    .cloned()
+                    .chain(relative.parent().into_iter().flat_map(|dir| {
+                        dir.iter()
+                            .map(|name| sanitize_test_name(&name.to_string_lossy()))
+                    }))
+                    .chain(doc.module)
+                    .collect();
+                docs.push(doc);
+            }
+            watched.push(book_dir.join("book.toml"));
+            watched.push(src_dir.join("SUMMARY.md"));
+        }
+
+        let template_library = self.template_library(&root_dir, &mut watched)?;
+        // The library's files aren't documents, even when they are found
+        // alongside them
+        docs.retain(|doc| !template_library.contains(&root_dir.join(&doc.path)));
+
+        let crate_name = crate_name(&root_dir);
+        Ok(Config {
+            out_dir,
+            root_dir,
+            out_file,
+            target_dir: self.target_dir.clone(),
+            target_triple,
+            docs,
+            watched,
+            batch: self.batch,
+            naming: self.naming.clone(),
+            timeout: self.timeout,
+            legacy_templates: self.legacy_templates,
+            wrap_main: self.wrap_main,
+            template_library,
+            crate_name,
+        })
+    }
+
+    /// Lists the files of the template library, in the order their
+    /// templates override each other.
+    fn template_library(
+        &self,
+        root_dir: &Path,
+        watched: &mut Vec<PathBuf>,
+    ) -> Result<Vec<PathBuf>, Error> {
+        if self.templates.is_empty() {
+            let default = root_dir.join("skeptic-templates.md");
+            if default.is_file() {
+                watched.push(default.clone());
+                return Ok(vec![default]);
+            }
+            return Ok(Vec::new());
+        }
+
+        let mut files = Vec::new();
+        for path in &self.templates {
+            let path = root_dir.join(path);
+            // Watching the directory catches files being added to it
+            watched.push(path.clone());
+            if !path.is_dir() {
+                files.push(path);
+                continue;
+            }
+            let mut dir_files = Vec::new();
+            for entry in std::fs::read_dir(&path)? {
+                let file = entry?.path();
+                if file.is_file() && file.extension().is_some_and(|ext| ext == "md") {
+                    dir_files.push(file);
+                }
+            }
+            dir_files.sort();
+            files.extend(dir_files);
+        }
+        Ok(files)
+    }
+}
+
+/// An example extracted from a document.
+///
+/// Returned by [`Skeptic::examples`].
+#[derive(Clone, Debug)]
+pub struct Example {
+    name: String,
+    path: String,
+    ignore: bool,
+    check: Check,
+    snippet: rt::Snippet,
+}
+
+#[derive(Clone, Debug)]
+enum Check {
+    Run,
+    Compile,
+    CompileFail(Vec<String>),
+}
+
+impl Example {
+    /// The name of the test that would be generated for the example.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The path of the generated test, including the modules of its
+    /// document and sections, such as `readme::usage::readme_sect_usage_line_12`.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Whether the example is marked `ignore`.
+    pub fn is_ignored(&self) -> bool {
+        self.ignore
+    }
+
+    /// Compiles, and unless it is marked `no_run`, runs the example,
+    /// following its info string.
+    pub fn check(&self) -> Result<(), Error> {
+        match self.check {
+            Check::Run => self.snippet.try_run(),
+            Check::Compile => self.snippet.try_compile(),
+            Check::CompileFail(ref error_codes) => {
+                let error_codes = error_codes.iter().map(String::as_str).collect::<Vec<_>>();
+                self.snippet.try_compile_fail(&error_codes)
+            }
+        }
+    }
+}
+
+/// What a test name can be made of, passed to the naming scheme set with
+/// [`Skeptic::test_names`].
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct TestNameParts {
+    /// The document containing the code block.
+    pub path: PathBuf,
+    /// The document's file name without its extension, sanitized for use
+    /// in a test name.
+    pub file_stem: String,
+    /// The heading the code block is under, sanitized for use in a test
+    /// name.
+    pub section: Option<String>,
+    /// Line of the code block's opening fence.
+    pub line: usize,
+    /// Position of the code block among the document's tests, starting at
+    /// zero.
+    pub index: usize,
+}
+
+impl TestNameParts {
+    /// The name skeptic gives the test by default, such as
+    /// `readme_sect_usage_line_12`.
+    pub fn default_name(&self) -> String {
+        default_test_name(&self.file_stem, self.section.as_deref(), self.line)
+    }
+}
+
+#[derive(Clone)]
+struct Naming(Arc<dyn Fn(&TestNameParts) -> String + Send + Sync>);
+
+impl fmt::Debug for Naming {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Naming(..)")
+    }
+}
+
+fn cargo_env(name: &'static str) -> Result<String, Error> {
+    env::var(name).map_err(|_| Error::MissingEnv(name))
+}
+
+/// Reads the name of the library of the package in `root_dir`, if there is
+/// one. It is named after the package unless `[lib]` says otherwise.
+fn crate_name(root_dir: &Path) -> Option<String> {
+    let manifest_path = root_dir.join("Cargo.toml");
+    let metadata = cargo_metadata::MetadataCommand::new()
+        .manifest_path(&manifest_path)
+        .no_deps()
+        .exec()
+        .ok()?;
+    let manifest_path = manifest_path.canonicalize().ok()?;
+    let package = metadata.packages.iter().find(|package| {
+        package.manifest_path.canonicalize().ok().as_ref() == Some(&manifest_path)
+    })?;
+    let lib = package.targets.iter().find(|target| {
+        target
+            .kind
+            .iter()
+            .any(|kind| ["lib", "rlib", "dylib", "proc-macro"].contains(&kind.as_str()))
+    })?;
+    Some(lib.name.replace('-', "_"))
 }
 
 struct Config {
     out_dir: PathBuf,
     root_dir: PathBuf,
     out_file: PathBuf,
+    target_dir: Option<PathBuf>,
     target_triple: String,
-    docs: Vec<String>,
+    docs: Vec<Doc>,
+    /// Files besides the documents that affect the tests.
+    watched: Vec<PathBuf>,
+    batch: bool,
+    naming: Option<Naming>,
+    timeout: Option<Duration>,
+    legacy_templates: bool,
+    wrap_main: bool,
+    /// Files of templates shared by every document.
+    template_library: Vec<PathBuf>,
+    /// Name of the crate in the root directory, as used in paths.
+    crate_name: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+struct Doc {
+    path: PathBuf,
+    /// Whether code blocks are Rust unless labeled otherwise.
+    implicit_rust: bool,
+    /// Whether mdBook's include directives are expanded.
+    expand_includes: bool,
+    /// Path of the module the document's tests are generated in.
+    module: Vec<String>,
 }
 
-fn run(config: &Config) {
-    let tests = extract_tests(config).unwrap();
-    emit_tests(config, tests).unwrap();
+impl Doc {
+    fn new<P: AsRef<Path>>(path: P) -> Doc {
+        let path = path.as_ref();
+        let module = path
+            .file_stem()
+            .map(|stem| sanitize_test_name(&stem.to_string_lossy()))
+            .into_iter()
+            .collect();
+        Doc {
+            path: path.to_owned(),
+            implicit_rust: false,
+            expand_includes: false,
+            module,
+        }
+    }
+}
+
+fn run(config: &Config) -> Result<(), Error> {
+    let tests = extract_tests(config)?;
+    for doc_test in &tests.doc_tests {
+        for path in &doc_test.includes {
+            println!("cargo:rerun-if-changed={}", path.display());
+        }
+    }
+    emit_tests(config, tests)
 }
 
 struct Test {
     name: String,
+    /// Whether the name was given in the info string.
+    explicit_name: bool,
+    /// The last level one or two heading above the code block.
+    section: Option<String>,
+    /// The headings of every level the code block is nested in.
+    sections: Vec<String>,
+    line: usize,
     text: Vec<String>,
     ignore: bool,
     no_run: bool,
     should_panic: bool,
+    compile_fail: bool,
+    error_codes: Vec<String>,
+    edition: Option<String>,
     template: Option<String>,
+    expected_output: Option<String>,
+    args: Vec<String>,
+    env: Vec<(String, String)>,
+    stdin: Option<String>,
+    timeout: Option<Duration>,
+    exit_code: Option<i32>,
+    /// Why an attribute of the info string couldn't be used, if it couldn't.
+    invalid: Option<String>,
+    /// The code wrapped around the example in place of a template, when
+    /// wrapping examples in `main`.
+    main_wrapper: Option<(String, String)>,
 }
 
 struct DocTestSuite {
@@ -151,71 +753,304 @@ struct DocTest {
     path: PathBuf,
     old_template: Option<String>,
     tests: Vec<Test>,
-    templates: HashMap<String, String>,
+    /// The templates of the library and of the document's `.skt.md` file.
+    templates: HashMap<String, Template>,
+    /// Files included into the document by mdBook directives.
+    includes: Vec<PathBuf>,
+    module: Vec<String>,
 }
 
-fn extract_tests(config: &Config) -> Result<DocTestSuite, IoError> {
+/// A template, composed with the templates it extends, with the file it
+/// was read from.
+#[derive(Clone, Debug)]
+struct Template {
+    text: String,
+    file: PathBuf,
+}
+
+/// A template as written in a templates file.
+#[derive(Clone, Debug)]
+struct TemplateDef {
+    /// The code block without a `slot=`, which fills in the `{{snippet}}`
+    /// of the extended template.
+    body: Option<String>,
+    /// The contents of the `{{slot:NAME}}` placeholders of the extended
+    /// template, by name.
+    slots: Vec<(String, String)>,
+    /// Name of the extended template, without the `skt-` prefix.
+    extends: Option<String>,
+    file: PathBuf,
+}
+
+fn extract_tests(config: &Config) -> Result<DocTestSuite, Error> {
+    let mut library = HashMap::new();
+    for file in &config.template_library {
+        library.extend(load_templates(file)?);
+    }
+
     let mut doc_tests = Vec::new();
+    let mut missing = Vec::new();
     for doc in &config.docs {
         let path = &mut config.root_dir.clone();
-        path.push(doc);
-        let new_tests = extract_tests_from_file(path)?;
+        path.push(&doc.path);
+        let mut new_tests = extract_tests_from_file(path, doc, &library, config.legacy_templates)?;
+        if let Some(ref naming) = config.naming {
+            rename_tests(&mut new_tests, naming);
+        }
+        if config.wrap_main && new_tests.old_template.is_none() {
+            for test in &mut new_tests.tests {
+                if test.template.is_none() {
+                    wrap_in_main(test, config.crate_name.as_deref());
+                }
+            }
+        }
+        missing.extend(missing_templates(&new_tests, &config.template_library));
         doc_tests.push(new_tests);
     }
+    if !missing.is_empty() {
+        return Err(Error::MissingTemplates(missing));
+    }
+    check_test_names(&doc_tests)?;
     Ok(DocTestSuite { doc_tests })
 }
 
+/// Applies a naming scheme to the tests not named in their info string.
+fn rename_tests(doc_test: &mut DocTest, naming: &Naming) {
+    let file_stem = sanitize_test_name(
+        &doc_test
+            .path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy())
+            .unwrap_or_default(),
+    );
+    for (index, test) in doc_test.tests.iter_mut().enumerate() {
+        if test.explicit_name {
+            continue;
+        }
+        let parts = TestNameParts {
+            path: doc_test.path.clone(),
+            file_stem: file_stem.clone(),
+            section: test.section.clone(),
+            line: test.line - 1,
+            index,
+        };
+        test.name = sanitize_test_name(&(naming.0)(&parts));
+    }
+}
+
+/// Checks that test names are identifiers and that no two tests in the same
+/// module share one.
+fn check_test_names(doc_tests: &[DocTest]) -> Result<(), Error> {
+    let mut invalid = Vec::new();
+    let mut locations = HashMap::<(Vec<String>, &str), Vec<(PathBuf, usize)>>::new();
+    let mut names = Vec::new();
+    for doc_test in doc_tests {
+        for test in &doc_test.tests {
+            let location = (doc_test.path.clone(), test.line - 1);
+            if !is_test_name(&test.name) {
+                invalid.push(InvalidTestName {
+                    name: test.name.clone(),
+                    path: location.0,
+                    line: location.1,
+                });
+                continue;
+            }
+            let key = (test_module(doc_test, test), test.name.as_str());
+            let entry = locations.entry(key.clone()).or_default();
+            if entry.is_empty() {
+                names.push(key);
+            }
+            entry.push(location);
+        }
+    }
+    if !invalid.is_empty() {
+        return Err(Error::InvalidTestNames(invalid));
+    }
+
+    let duplicates = names
+        .into_iter()
+        .filter_map(|key| {
+            let locations = locations.remove(&key)?;
+            if locations.len() < 2 {
+                return None;
+            }
+            Some(DuplicateTestName {
+                name: key.1.to_owned(),
+                locations,
+            })
+        })
+        .collect::<Vec<_>>();
+    if !duplicates.is_empty() {
+        return Err(Error::DuplicateTestNames(duplicates));
+    }
+    Ok(())
+}
+
+/// Whether a sanitized name can be used as the name of a test function.
+///
+/// Besides keywords, this rules out `__skeptic`, the name generated tests
+/// refer to skeptic by.
+fn is_test_name(name: &str) -> bool {
+    const KEYWORDS: &[&str] = &[
+        "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "crate",
+        "do", "dyn", "else", "enum", "extern", "false", "final", "fn", "for", "if", "impl", "in",
+        "let", "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref",
+        "return", "self", "static", "struct", "super", "trait", "true", "try", "type", "typeof",
+        "unsafe", "unsized", "use", "virtual", "where", "while", "yield",
+    ];
+    match name.chars().next() {
+        Some(first) => !first.is_ascii_digit() && !KEYWORDS.contains(&name) && name != "__skeptic",
+        None => false,
+    }
+}
+
+/// Finds the code blocks of a document that refer to undefined templates.
+fn missing_templates(doc_test: &DocTest, library: &[PathBuf]) -> Vec<MissingTemplate> {
+    let mut available = doc_test.templates.keys().cloned().collect::<Vec<_>>();
+    available.sort();
+    doc_test
+        .tests
+        .iter()
+        .filter_map(|test| {
+            let template = test.template.as_ref()?;
+            if doc_test.templates.contains_key(template) {
+                return None;
+            }
+            Some(MissingTemplate {
+                template: template.clone(),
+                path: doc_test.path.clone(),
+                line: test.line - 1,
+                templates_file: templates_file(&doc_test.path),
+                library: library.to_vec(),
+                available: available.clone(),
+            })
+        })
+        .collect()
+}
+
 enum Buffer {
     None,
     Code(Vec<String>),
+    Output(String),
+    Stdin(String),
     Heading(String),
 }
 
-fn extract_tests_from_file(path: &Path) -> Result<DocTest, IoError> {
+fn extract_tests_from_file(
+    path: &Path,
+    doc: &Doc,
+    library: &HashMap<String, TemplateDef>,
+    legacy_templates: bool,
+) -> Result<DocTest, Error> {
     let mut file = File::open(path)?;
     let s = &mut String::new();
     file.read_to_string(s)?;
 
-    let file_stem = &sanitize_test_name(path.file_stem().unwrap().to_str().unwrap());
+    let file_stem = &sanitize_test_name(&path.file_stem().unwrap().to_string_lossy());
+
+    let mut includes = Vec::new();
+    let tests = if doc.expand_includes {
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        let expansion = mdbook::expand_includes(s, dir)?;
+        includes = expansion.included;
+        // Lines are numbered as in the chapter, so that test names don't
+        // change when included files do
+        extract_mapped_tests(&expansion.text, file_stem, doc.implicit_rust, |line| {
+            expansion.lines[line]
+        })
+    } else {
+        extract_tests_from_string(s, file_stem, doc.implicit_rust)
+    };
 
-    let tests = extract_tests_from_string(s, file_stem);
+    if let Some(test) = tests.0.iter().find(|test| test.invalid.is_some()) {
+        return Err(Error::InfoString(format!(
+            "{}:{}: {}",
+            path.display(),
+            test.line - 1,
+            test.invalid.as_ref().unwrap()
+        )));
+    }
 
-    let templates = load_templates(path)?;
+    // The document's own templates take precedence over the library's.
+    // Templates are composed per document, so that overriding a template
+    // also changes the templates extending it.
+    let mut defs = library.clone();
+    defs.extend(load_templates(&templates_file(path))?);
+    let templates = compose_templates(&defs, legacy_templates)?;
 
     Ok(DocTest {
         path: path.to_owned(),
         old_template: tests.1,
         tests: tests.0,
         templates,
+        includes,
+        module: doc.module.clone(),
     })
 }
 
-fn extract_tests_from_string(s: &str, file_stem: &str) -> (Vec<Test>, Option<String>) {
-    let mut tests = Vec::new();
+fn extract_tests_from_string(
+    s: &str,
+    file_stem: &str,
+    implicit_rust: bool,
+) -> (Vec<Test>, Option<String>) {
+    extract_mapped_tests(s, file_stem, implicit_rust, |line| line)
+}
+
+/// Extracts tests, numbering lines through `line_map`.
+fn extract_mapped_tests<F: Fn(usize) -> usize>(
+    s: &str,
+    file_stem: &str,
+    implicit_rust: bool,
+    line_map: F,
+) -> (Vec<Test>, Option<String>) {
+    let mut tests: Vec<Test> = Vec::new();
     let mut buffer = Buffer::None;
     let parser = Parser::new(s);
     let mut section = None;
+    let mut sections: Vec<(HeadingLevel, String)> = Vec::new();
     let mut code_block_start = 0;
     // Oh this isn't actually a test but a legacy template
     let mut old_template = None;
+    // Whether an output or stdin block would belong to the last test
+    let mut awaiting_output = false;
 
     for (event, range) in parser.into_offset_iter() {
-        let line_number = bytecount::count(&s.as_bytes()[0..range.start], b'\n');
+        let line_number = line_map(bytecount::count(&s.as_bytes()[0..range.start], b'\n'));
         match event {
-            Event::Start(Tag::Heading(level, ..)) if level < HeadingLevel::H3 => {
+            Event::Start(Tag::Heading(..)) => {
                 buffer = Buffer::Heading(String::new());
             }
-            Event::End(Tag::Heading(level, ..)) if level < HeadingLevel::H3 => {
+            Event::End(Tag::Heading(level, ..)) => {
                 let cur_buffer = mem::replace(&mut buffer, Buffer::None);
                 if let Buffer::Heading(sect) = cur_buffer {
-                    section = Some(sanitize_test_name(&sect));
+                    let sect = sanitize_test_name(&sect);
+                    // Test names only use the top levels, so that they
+                    // don't change when subsections are added
+                    if level < HeadingLevel::H3 {
+                        section = Some(sect.clone());
+                    }
+                    while sections.last().is_some_and(|&(last, _)| last >= level) {
+                        sections.pop();
+                    }
+                    sections.push((level, sect));
                 }
             }
-            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(ref info))) => {
-                let code_block_info = parse_code_block_info(info);
+            Event::Start(Tag::CodeBlock(ref kind)) => {
+                let code_block_info = match code_block_kind_info(kind, implicit_rust) {
+                    Some(info) => info,
+                    None => continue,
+                };
+                // Both an output and a stdin block may follow a test
+                let attached =
+                    awaiting_output && (code_block_info.is_output || code_block_info.is_stdin);
                 if code_block_info.is_rust {
                     buffer = Buffer::Code(Vec::new());
+                } else if attached && code_block_info.is_output {
+                    buffer = Buffer::Output(String::new());
+                } else if attached {
+                    buffer = Buffer::Stdin(String::new());
                 }
+                awaiting_output = attached;
             }
             Event::Text(text) => {
                 if let Buffer::Code(ref mut buf) = buffer {
@@ -225,27 +1060,59 @@ fn extract_tests_from_string(s: &str, file_stem: &str) -> (Vec<Test>, Option<Str
                     buf.extend(text.lines().map(|s| format!("{}\n", s)));
                 } else if let Buffer::Heading(ref mut buf) = buffer {
                     buf.push_str(&text);
+                } else if let Buffer::Output(ref mut buf) | Buffer::Stdin(ref mut buf) = buffer {
+                    buf.push_str(&text);
                 }
             }
-            Event::End(Tag::CodeBlock(CodeBlockKind::Fenced(ref info))) => {
-                let code_block_info = parse_code_block_info(info);
-                if let Buffer::Code(buf) = mem::replace(&mut buffer, Buffer::None) {
+            Event::End(Tag::CodeBlock(ref kind)) => {
+                let code_block_info = match code_block_kind_info(kind, implicit_rust) {
+                    Some(info) => info,
+                    None => continue,
+                };
+                let cur_buffer = mem::replace(&mut buffer, Buffer::None);
+                if let Buffer::Output(output) = cur_buffer {
+                    if let Some(test) = tests.last_mut() {
+                        test.expected_output = Some(output);
+                    }
+                } else if let Buffer::Stdin(input) = cur_buffer {
+                    if let Some(test) = tests.last_mut() {
+                        test.stdin = Some(input);
+                    }
+                } else if let Buffer::Code(buf) = cur_buffer {
                     if code_block_info.is_old_template {
                         old_template = Some(buf.into_iter().collect())
                     } else {
-                        let name = if let Some(ref section) = section {
-                            format!("{}_sect_{}_line_{}", file_stem, section, code_block_start)
-                        } else {
-                            format!("{}_line_{}", file_stem, code_block_start)
+                        let explicit_name = code_block_info.name.is_some();
+                        let name = match code_block_info.name {
+                            Some(ref name) => sanitize_test_name(name),
+                            None => {
+                                default_test_name(file_stem, section.as_deref(), code_block_start)
+                            }
                         };
                         tests.push(Test {
                             name,
+                            explicit_name,
+                            section: section.clone(),
+                            sections: sections.iter().map(|(_, sect)| sect.clone()).collect(),
+                            line: code_block_start + 1,
                             text: buf,
                             ignore: code_block_info.ignore,
                             no_run: code_block_info.no_run,
                             should_panic: code_block_info.should_panic,
+                            compile_fail: code_block_info.compile_fail,
+                            error_codes: code_block_info.error_codes,
+                            edition: code_block_info.edition,
                             template: code_block_info.template,
+                            expected_output: None,
+                            args: code_block_info.args,
+                            env: code_block_info.env,
+                            stdin: None,
+                            timeout: code_block_info.timeout,
+                            exit_code: code_block_info.exit_code,
+                            invalid: code_block_info.invalid,
+                            main_wrapper: None,
                         });
+                        awaiting_output = true;
                     }
                 }
             }
@@ -255,12 +1122,20 @@ fn extract_tests_from_string(s: &str, file_stem: &str) -> (Vec<Test>, Option<Str
     (tests, old_template)
 }
 
-fn load_templates(path: &Path) -> Result<HashMap<String, String>, IoError> {
+/// The file the templates of a document are defined in.
+fn templates_file(path: &Path) -> PathBuf {
     let file_name = format!(
         "{}.skt.md",
         path.file_name().expect("no file name").to_string_lossy()
     );
-    let path = path.with_file_name(&file_name);
+    path.with_file_name(file_name)
+}
+
+/// Reads the `skt-` templates defined in a markdown file, if it exists.
+///
+/// The code blocks of a template with a `slot=` attribute are gathered
+/// into the same definition as its body.
+fn load_templates(path: &Path) -> Result<HashMap<String, TemplateDef>, IoError> {
     if !path.exists() {
         return Ok(HashMap::new());
     }
@@ -291,7 +1166,20 @@ fn load_templates(path: &Path) -> Result<HashMap<String, String>, IoError> {
                 let code_block_info = parse_code_block_info(info);
                 if let Some(buf) = code_buffer.take() {
                     if let Some(t) = code_block_info.template {
-                        map.insert(t, buf.into_iter().collect());
+                        let def = map.entry(t).or_insert_with(|| TemplateDef {
+                            body: None,
+                            slots: Vec::new(),
+                            extends: None,
+                            file: path.to_owned(),
+                        });
+                        let text = buf.into_iter().collect();
+                        match code_block_info.slot {
+                            Some(slot) => def.slots.push((slot, text)),
+                            None => def.body = Some(text),
+                        }
+                        if code_block_info.extends.is_some() {
+                            def.extends = code_block_info.extends;
+                        }
                     }
                 }
             }
@@ -302,6 +1190,150 @@ fn load_templates(path: &Path) -> Result<HashMap<String, String>, IoError> {
     Ok(map)
 }
 
+/// Composes each template with the templates it extends.
+fn compose_templates(
+    defs: &HashMap<String, TemplateDef>,
+    legacy_templates: bool,
+) -> Result<HashMap<String, Template>, Error> {
+    let mut names = defs.keys().collect::<Vec<_>>();
+    names.sort();
+    let mut composed = HashMap::new();
+    for name in names {
+        if legacy_templates && defs[name].extends.is_some() {
+            return Err(template_def_error(
+                &defs[name],
+                name,
+                "templates can only be extended with `{{snippet}}` placeholders, not legacy ones",
+            ));
+        }
+        compose_template(defs, name, &mut Vec::new(), &mut composed)?;
+    }
+    Ok(composed)
+}
+
+/// Composes a template, `chain` holding the templates extending it that
+/// are being composed, to detect cycles.
+fn compose_template(
+    defs: &HashMap<String, TemplateDef>,
+    name: &str,
+    chain: &mut Vec<String>,
+    composed: &mut HashMap<String, Template>,
+) -> Result<Template, Error> {
+    let def = &defs[name];
+    if let Some(start) = chain.iter().position(|extending| extending == name) {
+        let cycle = chain[start..]
+            .iter()
+            .map(|name| {
+                format!(
+                    "skt-{} extends skt-{}",
+                    name,
+                    defs[name].extends.as_ref().unwrap()
+                )
+            })
+            .collect::<Vec<_>>();
+        return Err(template_def_error(
+            def,
+            name,
+            &format!("is extended in a cycle: {}", cycle.join(", ")),
+        ));
+    }
+    if let Some(template) = composed.get(name) {
+        return Ok(template.clone());
+    }
+
+    let text = match def.extends {
+        Some(ref parent) => {
+            if !defs.contains_key(parent) {
+                return Err(template_def_error(
+                    def,
+                    name,
+                    &format!("extends skt-{}, which isn't defined", parent),
+                ));
+            }
+            chain.push(name.to_owned());
+            let parent_template = compose_template(defs, parent, chain, composed)?;
+            chain.pop();
+            fill_slots(&parent_template.text, def)
+                .map_err(|msg| template_def_error(def, name, &format!("skt-{} {}", parent, msg)))?
+        }
+        None if def.slots.is_empty() => def.body.clone().unwrap_or_default(),
+        None => {
+            return Err(template_def_error(
+                def,
+                name,
+                "fills in slots, but doesn't extend a template",
+            ))
+        }
+    };
+    let template = Template {
+        text,
+        file: def.file.clone(),
+    };
+    composed.insert(name.to_owned(), template.clone());
+    Ok(template)
+}
+
+/// Fills in the `{{snippet}}` and `{{slot:NAME}}` placeholders of an
+/// extended template with the body and slots of a template extending it.
+/// Placeholders without a value are kept for templates further down.
+fn fill_slots(parent: &str, def: &TemplateDef) -> Result<String, String> {
+    let mut out = String::new();
+    let mut filled = Vec::new();
+    let mut filled_body = false;
+    let mut rest = parent;
+    while let Some((start, name, end)) = find_placeholder(rest) {
+        let value = match name.strip_prefix("slot:") {
+            Some(slot) => {
+                let slot = slot.trim();
+                filled.push(slot);
+                def.slots
+                    .iter()
+                    .find(|(name, _)| name == slot)
+                    .map(|(_, value)| value)
+            }
+            None if name == "snippet" => {
+                filled_body = true;
+                def.body.as_ref()
+            }
+            None => None,
+        };
+        out.push_str(&rest[..start]);
+        match value {
+            // A trailing newline would end up in the middle of a line
+            Some(value) => out.push_str(value.strip_suffix('\n').unwrap_or(value)),
+            None => out.push_str(&rest[start..end]),
+        }
+        rest = &rest[end..];
+    }
+    out.push_str(rest);
+    // Such as a legacy `format!` template, whose `{}` can't be filled in
+    if def.body.is_some() && !filled_body {
+        return Err(String::from(
+            "has no `{{snippet}}` placeholder for the body of the template",
+        ));
+    }
+    match def
+        .slots
+        .iter()
+        .find(|(name, _)| !filled.contains(&name.as_str()))
+    {
+        Some((slot, _)) => Err(format!("has no `{{{{slot:{}}}}}` placeholder", slot)),
+        None => Ok(out),
+    }
+}
+
+fn template_def_error(def: &TemplateDef, name: &str, msg: &str) -> Error {
+    Error::Template(format!("{}: skt-{} {}", def.file.display(), name, msg))
+}
+
+fn default_test_name(file_stem: &str, section: Option<&str>, line: usize) -> String {
+    if let Some(section) = section {
+        format!("{}_sect_{}_line_{}", file_stem, section, line)
+    } else {
+        format!("{}_line_{}", file_stem, line)
+    }
+}
+
 fn sanitize_test_name(s: &str) -> String {
     s.to_ascii_lowercase()
         .chars()
@@ -319,22 +1351,107 @@ fn sanitize_test_name(s: &str) -> String {
         .join("_")
 }
 
+/// Parses the info of a code block, or returns `None` for indented code
+/// blocks unless code blocks are Rust by default.
+fn code_block_kind_info(kind: &CodeBlockKind, implicit_rust: bool) -> Option<CodeBlockInfo> {
+    match *kind {
+        CodeBlockKind::Fenced(ref info) => Some(parse_info(info, implicit_rust)),
+        CodeBlockKind::Indented if implicit_rust => Some(parse_info("", true)),
+        CodeBlockKind::Indented => None,
+    }
+}
+
 fn parse_code_block_info(info: &str) -> CodeBlockInfo {
-    // Same as rustdoc
-    let tokens = info.split(|c: char| !(c == '_' || c == '-' || c.is_alphanumeric()));
+    parse_info(info, false)
+}
+
+/// Parses an info string. When `implicit_rust` is set, blocks count as
+/// Rust unless the info string has words skeptic doesn't know.
+fn parse_info(info: &str, implicit_rust: bool) -> CodeBlockInfo {
+    let tokens = info_tokens(info);
 
     let mut seen_rust_tags = false;
     let mut seen_other_tags = false;
     let mut info = CodeBlockInfo {
-        is_rust: false,
+        is_rust: implicit_rust,
         should_panic: false,
         ignore: false,
         no_run: false,
+        compile_fail: false,
+        error_codes: Vec::new(),
+        edition: None,
         is_old_template: false,
+        is_output: false,
+        is_stdin: false,
         template: None,
+        extends: None,
+        slot: None,
+        name: None,
+        args: Vec::new(),
+        env: Vec::new(),
+        timeout: None,
+        exit_code: None,
+        invalid: None,
     };
 
     for token in tokens {
+        let token = match token {
+            InfoToken::Word(word) => word,
+            InfoToken::Attribute("name", value) => {
+                info.name = Some(value);
+                seen_rust_tags = true;
+                continue;
+            }
+            InfoToken::Attribute("extends", value) => {
+                let parent = value.strip_prefix("skt-").unwrap_or(&value);
+                info.extends = Some(parent.to_owned());
+                seen_rust_tags = true;
+                continue;
+            }
+            InfoToken::Attribute("slot", value) => {
+                info.slot = Some(value);
+                seen_rust_tags = true;
+                continue;
+            }
+            InfoToken::Attribute("args", value) => {
+                info.args
+                    .extend(value.split_whitespace().map(str::to_owned));
+                seen_rust_tags = true;
+                continue;
+            }
+            InfoToken::Attribute("timeout", value) => {
+                match value.parse() {
+                    Ok(secs) => info.timeout = Some(Duration::from_secs(secs)),
+                    Err(_) => {
+                        info.invalid =
+                            Some(format!("`timeout={}` is not a number of seconds", value))
+                    }
+                }
+                seen_rust_tags = true;
+                continue;
+            }
+            InfoToken::Attribute("exit_code", value) => {
+                match value.parse() {
+                    Ok(code) => info.exit_code = Some(code),
+                    Err(_) => {
+                        info.invalid = Some(format!("`exit_code={}` is not an exit code", value))
+                    }
+                }
+                seen_rust_tags = true;
+                continue;
+            }
+            InfoToken::Attribute("env", value) => {
+                let mut parts = value.splitn(2, '=');
+                let key = parts.next().unwrap_or("").to_owned();
+                info.env.push((key, parts.next().unwrap_or("").to_owned()));
+                seen_rust_tags = true;
+                continue;
+            }
+            InfoToken::Attribute(..) => {
+                seen_other_tags = true;
+                continue;
+            }
+        };
         match token {
             "" => {}
             "rust" => {
@@ -353,10 +1470,31 @@ fn parse_code_block_info(info: &str) -> CodeBlockInfo {
                 info.no_run = true;
                 seen_rust_tags = true;
             }
+            "compile_fail" => {
+                info.compile_fail = true;
+                seen_rust_tags = true;
+            }
+            "edition2015" | "edition2018" | "edition2021" | "edition2024" => {
+                info.edition = Some(token["edition".len()..].to_owned());
+                seen_rust_tags = true;
+            }
+            _ if is_error_code(token) => {
+                info.error_codes.push(token.to_owned());
+                seen_rust_tags = true;
+            }
             "skeptic-template" => {
                 info.is_old_template = true;
                 seen_rust_tags = true
             }
+            "skt-output" => {
+                info.is_output = true;
+                // Output blocks are never Rust
+                seen_other_tags = true;
+            }
+            "skt-stdin" => {
+                info.is_stdin = true;
+                seen_other_tags = true;
+            }
             _ if token.starts_with("skt-") => {
                 info.template = Some(token[4..].to_string());
                 seen_rust_tags = true;
@@ -367,40 +1505,382 @@ fn parse_code_block_info(info: &str) -> CodeBlockInfo {
 
     info.is_rust &= !seen_other_tags || seen_rust_tags;
 
+    // An example that isn't run can't panic. Before `should_panic` checked
+    // for a panic, this combination passed when the example didn't compile.
+    if info.no_run && info.should_panic {
+        info.should_panic = false;
+        info.compile_fail = true;
+    }
+
     info
 }
 
+/// Whether the token is a compiler error code such as `E0382`.
+fn is_error_code(token: &str) -> bool {
+    token.len() == 5 && token.starts_with('E') && token[1..].bytes().all(|b| b.is_ascii_digit())
+}
+
+/// A word of an info string, or a `key=value` attribute.
+#[derive(Debug, PartialEq)]
+enum InfoToken<'a> {
+    Word(&'a str),
+    Attribute(&'a str, String),
+}
+
+/// Splits an info string into words the way rustdoc does, at anything but
+/// letters, digits, `_` and `-`. A word directly followed by `=` is an
+/// attribute, whose value runs up to the next comma or whitespace, or may
+/// be enclosed in double quotes to contain those, with `\"` and `\\`
+/// escaped.
+fn info_tokens(info: &str) -> Vec<InfoToken<'_>> {
+    let is_word_char = |c: char| c == '_' || c == '-' || c.is_alphanumeric();
+    let mut tokens = Vec::new();
+    let mut rest = info;
+    loop {
+        rest = rest.trim_start_matches(|c: char| !is_word_char(c));
+        if rest.is_empty() {
+            break;
+        }
+        let end = rest.find(|c: char| !is_word_char(c)).unwrap_or(rest.len());
+        let word = &rest[..end];
+        rest = &rest[end..];
+        let value = match rest.strip_prefix('=') {
+            Some(value) => value,
+            None => {
+                tokens.push(InfoToken::Word(word));
+                continue;
+            }
+        };
+        let (value, len) = if let Some(quoted) = value.strip_prefix('"') {
+            let mut unquoted = String::new();
+            let mut chars = quoted.char_indices();
+            let mut len = quoted.len();
+            while let Some((i, c)) = chars.next() {
+                match c {
+                    '"' => {
+                        len = i + 1;
+                        break;
+                    }
+                    '\\' => unquoted.extend(chars.next().map(|(_, c)| c)),
+                    _ => unquoted.push(c),
+                }
+            }
+            (unquoted, 1 + len)
+        } else {
+            let end = value
+                .find(|c: char| c == ',' || c.is_whitespace())
+                .unwrap_or(value.len());
+            (value[..end].to_owned(), end)
+        };
+        tokens.push(InfoToken::Attribute(word, value));
+        rest = &rest[1 + len..];
+    }
+    tokens
+}
+
 struct CodeBlockInfo {
     is_rust: bool,
     should_panic: bool,
     ignore: bool,
     no_run: bool,
+    compile_fail: bool,
+    error_codes: Vec<String>,
+    edition: Option<String>,
     is_old_template: bool,
+    is_output: bool,
+    is_stdin: bool,
     template: Option<String>,
+    /// The template a template extends, for templates.
+    extends: Option<String>,
+    /// The slot of the extended template a template's code block fills in.
+    slot: Option<String>,
+    name: Option<String>,
+    args: Vec<String>,
+    env: Vec<(String, String)>,
+    timeout: Option<Duration>,
+    exit_code: Option<i32>,
+    /// Why an attribute's value couldn't be used, if it couldn't.
+    invalid: Option<String>,
 }
 
-fn emit_tests(config: &Config, suite: DocTestSuite) -> Result<(), IoError> {
+fn emit_tests(config: &Config, suite: DocTestSuite) -> Result<(), Error> {
     let mut out = String::new();
+    let mut manifest = Vec::new();
 
-    // Test cases use the api from skeptic::rt
-    out.push_str("extern crate skeptic;\n");
+    // Test cases use the api from skeptic::rt, under a name that modules
+    // and tests can't take
+    out.push_str("extern crate skeptic as __skeptic;\n");
 
-    for doc_test in suite.doc_tests {
+    let mut tests = TestModule::default();
+    for (index, doc_test) in suite.doc_tests.iter().enumerate() {
+        // Diagnostics are reported relative to the crate, like rustc does
+        let source_path = doc_test
+            .path
+            .strip_prefix(&config.root_dir)
+            .unwrap_or(&doc_test.path);
+        let batch = if config.batch {
+            create_batch(config, doc_test, index, source_path)?
+        } else {
+            None
+        };
         for test in &doc_test.tests {
-            let test_string = {
-                if let Some(ref t) = test.template {
-                    let template = doc_test.templates.get(t).unwrap_or_else(|| {
-                        panic!("template {} not found for {}", t, doc_test.path.display())
-                    });
-                    create_test_runner(config, &Some(template.to_string()), test)?
-                } else {
-                    create_test_runner(config, &doc_test.old_template, test)?
-                }
-            };
-            out.push_str(&test_string);
+            let wrapper = test_wrapper(config, doc_test, test, source_path)?;
+            let batch = batch.as_ref().and_then(|batch| {
+                let offset = *batch.offsets.get(&test.name)?;
+                Some((batch.path.as_path(), offset))
+            });
+            let test_string = create_test_runner(config, &wrapper, test, source_path, batch)?;
+            let module = test_module(doc_test, test);
+            tests.insert(&module, test_string);
+            manifest.push(manifest_entry(
+                config,
+                doc_test,
+                test,
+                &wrapper,
+                &module,
+                source_path,
+            ));
         }
     }
-    write_if_contents_changed(&config.out_file, &out)
+    tests.write(&mut out, 0);
+    write_if_contents_changed(&config.out_file, &out)?;
+
+    let manifest = serde_json::json!({
+        "version": 1,
+        "tests": manifest,
+    });
+    let manifest = serde_json::to_string_pretty(&manifest).expect("manifest is valid JSON");
+    write_if_contents_changed(&manifest_file(&config.out_file), &manifest)?;
+    Ok(())
+}
+
+/// The manifest is written next to the generated tests, named after them:
+/// `skeptic-tests.rs` gets `skeptic-manifest.json`, `readme.rs` gets
+/// `readme-manifest.json`.
+fn manifest_file(out_file: &Path) -> PathBuf {
+    let stem = out_file
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let stem = stem.strip_suffix("-tests").unwrap_or(&stem);
+    out_file.with_file_name(format!("{}-manifest.json", stem))
+}
+
+/// Generated tests, nested in modules per document and heading.
+#[derive(Default)]
+struct TestModule {
+    tests: Vec<String>,
+    modules: Vec<(String, TestModule)>,
+}
+
+impl TestModule {
+    fn insert(&mut self, path: &[String], test: String) {
+        let (name, rest) = match path.split_first() {
+            Some(split) => split,
+            None => return self.tests.push(test),
+        };
+        let index = match self.modules.iter().position(|(n, _)| n == name) {
+            Some(index) => index,
+            None => {
+                self.modules.push((name.clone(), TestModule::default()));
+                self.modules.len() - 1
+            }
+        };
+        self.modules[index].1.insert(rest, test);
+    }
+
+    fn write(&self, out: &mut String, depth: usize) {
+        for test in &self.tests {
+            out.push_str(test);
+        }
+        let indent = "    ".repeat(depth);
+        for (name, module) in &self.modules {
+            out.push_str(&format!("{}mod {} {{\n", indent, name));
+            // In the 2015 edition, paths to skeptic in the tests are
+            // relative to the module
+            out.push_str(&format!(
+                "{0}    #[allow(unused_imports)]\n{0}    use super::__skeptic;\n",
+                indent
+            ));
+            module.write(out, depth + 1);
+            out.push_str(&format!("{}}}\n", indent));
+        }
+    }
+}
+
+/// Returns the path of the module a test is generated in.
+fn test_module(doc_test: &DocTest, test: &Test) -> Vec<String> {
+    let mut module = Vec::<String>::new();
+    for name in doc_test.module.iter().chain(&test.sections) {
+        let name = if is_test_name(name) {
+            name.clone()
+        } else {
+            format!("_{}", name)
+        };
+        // Empty headings are skipped, and so are headings repeating the
+        // name of their document, like `# Introduction` in `introduction.md`
+        if name != "_" && module.last() != Some(&name) {
+            module.push(name);
+        }
+    }
+    module
+}
+
+/// Describes a test for the manifest.
+fn manifest_entry(
+    config: &Config,
+    doc_test: &DocTest,
+    test: &Test,
+    wrapper: &(String, String),
+    module: &[String],
+    source_path: &Path,
+) -> serde_json::Value {
+    serde_json::json!({
+        "name": test.name,
+        "module": module.join("::"),
+        "file": source_path.to_string_lossy(),
+        "lines": {
+            "start": test.line,
+            "end": test.line + test.text.len().saturating_sub(1),
+        },
+        "ignore": test.ignore,
+        "no_run": test.no_run,
+        "should_panic": test.should_panic,
+        "compile_fail": test.compile_fail,
+        "error_codes": test.error_codes,
+        "edition": test.edition,
+        "template": template_name(doc_test, test),
+        "template_file": test_template(doc_test, test)
+            .map(|template| relative_path(config, &template.file)),
+        "expected_output": test.expected_output,
+        "args": test.args,
+        "env": test.env,
+        "stdin": test.stdin,
+        "timeout": test.timeout.map(|timeout| timeout.as_secs()),
+        "exit_code": test.exit_code,
+        "source": expand_test(wrapper, test),
+    })
+}
+
+/// A path relative to the root directory, as reported to users.
+fn relative_path(config: &Config, path: &Path) -> String {
+    path.strip_prefix(&config.root_dir)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// How the template of a test is referred to, such as `skt-foo`.
+fn template_name(doc_test: &DocTest, test: &Test) -> Option<String> {
+    match test.template {
+        Some(ref name) => Some(format!("skt-{}", name)),
+        None => doc_test
+            .old_template
+            .as_ref()
+            .map(|_| String::from("skeptic-template")),
+    }
+}
+
+/// Looks up the template a test is wrapped in.
+///
+/// Templates are checked to exist by `extract_tests`.
+fn test_template(doc_test: &DocTest, test: &Test) -> Option<Template> {
+    if let Some(ref t) = test.template {
+        let template = doc_test
+            .templates
+            .get(t)
+            .expect("missing templates are reported during extraction");
+        Some(template.clone())
+    } else {
+        doc_test.old_template.as_ref().map(|text| Template {
+            text: text.clone(),
+            file: doc_test.path.clone(),
+        })
+    }
+}
+
+/// Whether a test only needs to be compiled and run the standard way, so
+/// that it can share a binary with other tests.
+fn is_batchable(test: &Test) -> bool {
+    !(test.ignore || test.no_run || test.should_panic || test.compile_fail)
+        && test.edition.is_none()
+}
+
+/// Writes the batchable tests of a document into a single crate, each
+/// wrapped in a module named after the test, with a `main` that runs the
+/// test named by the `SKEPTIC_BATCH_TEST` environment variable.
+///
+/// Returns `None` if there are too few tests to be worth batching.
+fn create_batch(
+    config: &Config,
+    doc_test: &DocTest,
+    index: usize,
+    source_path: &Path,
+) -> Result<Option<Batch>, Error> {
+    let tests = doc_test
+        .tests
+        .iter()
+        .filter(|test| is_batchable(test))
+        .collect::<Vec<_>>();
+    if tests.len() < 2 {
+        return Ok(None);
+    }
+
+    let mut s = String::new();
+    let mut offsets = HashMap::new();
+    s.push_str(&format!(
+        "// Examples from {}, batched by skeptic\n",
+        doc_test.path.display()
+    ));
+    for test in &tests {
+        let (prefix, suffix) = test_wrapper(config, doc_test, test, source_path)?;
+        // The test's source starts with an empty line, which the line
+        // opening the module takes the place of
+        offsets.insert(test.name.clone(), s.matches('\n').count());
+        s.push_str(&format!("mod {} {{", test.name));
+        s.push_str(&format!(
+            "\n{}{}{}\n",
+            prefix,
+            create_test_input(&test.text),
+            suffix
+        ));
+        s.push_str("pub mod __skeptic {\n");
+        s.push_str("    pub fn run() -> impl ::std::process::Termination {\n");
+        s.push_str("        super::main()\n");
+        s.push_str("    }\n");
+        s.push_str("}\n");
+        s.push_str("}\n\n");
+    }
+    s.push_str("fn main() -> ::std::process::ExitCode {\n");
+    s.push_str(&format!(
+        "    match ::std::env::var({:?}).as_ref().map(String::as_str) {{\n",
+        rt::BATCH_TEST_VAR
+    ));
+    for test in &tests {
+        s.push_str(&format!(
+            "        Ok({:?}) => ::std::process::Termination::report({}::__skeptic::run()),\n",
+            test.name, test.name
+        ));
+    }
+    s.push_str("        _ => ::std::process::ExitCode::FAILURE,\n");
+    s.push_str("    }\n");
+    s.push_str("}\n");
+
+    let stem = doc_test.path.file_stem().unwrap().to_string_lossy();
+    let path = config.out_dir.join(format!(
+        "skeptic-batch-{}-{}.rs",
+        sanitize_test_name(&stem),
+        index
+    ));
+    write_if_contents_changed(&path, &s)?;
+    Ok(Some(Batch { path, offsets }))
+}
+
+struct Batch {
+    /// The batch crate's source file.
+    path: PathBuf,
+    /// How many lines into the file each test's source starts, by name.
+    offsets: HashMap<String, usize>,
 }
 
 /// Just like Rustdoc, ignore a "#" sign at the beginning of a line of code.
@@ -435,47 +1915,414 @@ fn create_test_input(lines: &[String]) -> String {
         .collect()
 }
 
+/// Returns the code preceding and following a test's example, from its
+/// template.
+fn test_wrapper(
+    config: &Config,
+    doc_test: &DocTest,
+    test: &Test,
+    source_path: &Path,
+) -> Result<(String, String), Error> {
+    let template = match test_template(doc_test, test) {
+        Some(template) => template,
+        None => return Ok(test.main_wrapper.clone().unwrap_or_default()),
+    };
+    let file = source_path.to_string_lossy().replace('\\', "/");
+    let vars = TemplateVars {
+        name: &test.name,
+        file: &file,
+        crate_name: config.crate_name.as_deref(),
+    };
+    let split = if config.legacy_templates || is_legacy_template(&template.text) {
+        split_format_template(&template.text)
+    } else {
+        split_template(&template.text, &vars)
+    };
+    split.map_err(|msg| {
+        Error::Template(format!(
+            "{}:{}: {} from {}: {}",
+            doc_test.path.display(),
+            test.line - 1,
+            template_name(doc_test, test).unwrap_or_default(),
+            relative_path(config, &template.file),
+            msg
+        ))
+    })
+}
+
+/// Wraps a test's example in `main` like rustdoc, unless it defines one.
+///
+/// The leading crate attributes and `extern crate` lines are moved before
+/// `main`, leaving empty lines in their place so that the lines of the
+/// example still follow each other.
+fn wrap_in_main(test: &mut Test, crate_name: Option<&str>) {
+    let mut hoisted = String::new();
+    let mut has_crate = false;
+    // Outer attributes, such as `#[macro_use]`, on the lines before an
+    // `extern crate` go along with it
+    let mut attributes = Vec::new();
+    for i in 0..test.text.len() {
+        let code = clean_omitted_line(&test.text[i]).trim();
+        let is_extern_crate =
+            code.starts_with("extern crate") || code.starts_with("#[macro_use] extern crate");
+        if code.starts_with("#![") || is_extern_crate {
+            if let Some(name) = crate_name {
+                has_crate |= is_extern_crate
+                    && code
+                        .split_whitespace()
+                        .any(|word| word.trim_end_matches(';') == name);
+            }
+            for j in attributes.drain(..).chain(Some(i)) {
+                let line = &mut test.text[j];
+                hoisted.push_str(clean_omitted_line(line));
+                *line = String::from("\n");
+            }
+        } else if code.starts_with("#[") {
+            attributes.push(i);
+        } else if !(code.is_empty() || code.starts_with("//")) {
+            break;
+        }
+    }
+
+    let code = create_test_input(&test.text);
+    if let Some(name) = crate_name {
+        if !has_crate && mentions(&code, name) {
+            hoisted.push_str(&format!("extern crate {};\n", name));
+        }
+    }
+    let tail = code
+        .lines()
+        .rev()
+        .map(str::trim)
+        .find(|line| !line.is_empty());
+    let (prefix, suffix) = if mentions(&code, "fn main") {
+        (hoisted, String::new())
+    } else if tail == Some("Ok(())") {
+        (
+            hoisted + "fn main() -> Result<(), Box<dyn std::error::Error>> {\n",
+            String::from("}\n"),
+        )
+    } else if tail.is_some_and(|tail| tail.ends_with("(())")) {
+        // The error type is given, as in `Ok::<(), io::Error>(())`
+        (
+            hoisted + "fn main() { fn _inner() -> Result<(), impl std::fmt::Debug> {\n",
+            String::from("} _inner().unwrap() }\n"),
+        )
+    } else {
+        (hoisted + "fn main() {\n", String::from("}\n"))
+    };
+    test.main_wrapper = Some((prefix, suffix));
+}
+
+/// Whether `code` contains `word` other than as part of a longer
+/// identifier.
+fn mentions(code: &str, word: &str) -> bool {
+    let is_ident_char = |c: char| c == '_' || c.is_alphanumeric();
+    code.match_indices(word).any(|(start, _)| {
+        let end = start + word.len();
+        !code[..start].chars().next_back().is_some_and(is_ident_char)
+            && !code[end..].chars().next().is_some_and(is_ident_char)
+    })
+}
+
+/// Values of the placeholders a template may use besides `{{snippet}}`.
+struct TemplateVars<'a> {
+    name: &'a str,
+    file: &'a str,
+    crate_name: Option<&'a str>,
+}
+
+/// Splits a template around its `{{snippet}}` placeholder, filling in the
+/// other placeholders. Braces are otherwise left as they are.
+fn split_template(template: &str, vars: &TemplateVars) -> Result<(String, String), String> {
+    let mut parts = vec![String::new()];
+    let mut rest = template;
+    while let Some((start, name, end)) = find_placeholder(rest) {
+        parts.last_mut().unwrap().push_str(&rest[..start]);
+        rest = &rest[end..];
+        if name.starts_with("slot:") {
+            // Slots left empty by the templates extending this one
+            continue;
+        }
+        let value = match name {
+            "snippet" if parts.len() == 1 => {
+                parts.push(String::new());
+                continue;
+            }
+            "snippet" => return Err(String::from("`{{snippet}}` is used more than once")),
+            "name" => vars.name,
+            "file" => vars.file,
+            "crate" => vars
+                .crate_name
+                .ok_or("`{{crate}}` is used, but the root directory has no library crate")?,
+            _ => return Err(format!("unknown placeholder `{{{{{}}}}}`", name)),
+        };
+        parts.last_mut().unwrap().push_str(value);
+    }
+    parts.last_mut().unwrap().push_str(rest);
+    if parts.len() == 1 {
+        return Err(String::from(
+            "the template has no `{{snippet}}` placeholder",
+        ));
+    }
+    let suffix = parts.pop().unwrap();
+    Ok((parts.pop().unwrap(), suffix))
+}
+
+/// Finds the next `{{name}}` or `{{slot:name}}` placeholder, returning
+/// where it starts, its name and where it ends.
+fn find_placeholder(s: &str) -> Option<(usize, &str, usize)> {
+    let is_name = |name: &str| {
+        !name.is_empty() && name.chars().all(|c| c == '_' || c.is_ascii_alphanumeric())
+    };
+    let mut from = 0;
+    while let Some(start) = s[from..].find("{{").map(|start| from + start) {
+        let end = s[start..].find("}}").map(|end| start + end)?;
+        let name = s[start + 2..end].trim();
+        if is_name(name.strip_prefix("slot:").map_or(name, str::trim)) {
+            return Some((start, name, end + 2));
+        }
+        // Not a placeholder, such as the braces of nested blocks. The
+        // second brace may still open one, as in `{{{snippet}}}`.
+        from = start + 1;
+    }
+    None
+}
+
+/// Whether a template is in the legacy `format!` syntax, which is read as
+/// such even without [`Skeptic::legacy_templates`]: it has no
+/// `{{snippet}}` placeholder, and `format!` would accept it with a single
+/// argument.
+fn is_legacy_template(template: &str) -> bool {
+    let mut rest = template;
+    while let Some((_, name, end)) = find_placeholder(rest) {
+        if name == "snippet" {
+            return false;
+        }
+        rest = &rest[end..];
+    }
+    split_format_template(template).is_ok()
+}
+
+/// Splits a template in the legacy `format!` syntax around its `{}`
+/// placeholder, turning doubled braces back into literal ones.
+///
+/// Templates that `format!` would have rejected with a single argument are
+/// reported, rather than producing tests that fail to compile.
+fn split_format_template(template: &str) -> Result<(String, String), String> {
+    let mut parts = vec![String::new()];
+    let mut chars = template.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match (ch, chars.peek()) {
+            ('{', Some('{')) | ('}', Some('}')) => {
+                chars.next();
+                parts.last_mut().unwrap().push(ch);
+            }
+            ('{', Some('}')) if parts.len() == 1 => {
+                chars.next();
+                parts.push(String::new());
+            }
+            ('{', Some('}')) => return Err(String::from("`{}` is used more than once")),
+            ('{', _) => return Err(String::from("unescaped `{`, literal braces are doubled")),
+            ('}', _) => return Err(String::from("unescaped `}`, literal braces are doubled")),
+            _ => parts.last_mut().unwrap().push(ch),
+        }
+    }
+    if parts.len() == 1 {
+        return Err(String::from("the template has no `{}` placeholder"));
+    }
+    let suffix = parts.pop().unwrap();
+    Ok((parts.pop().unwrap(), suffix))
+}
+
+/// Wraps a test in the code from its template, giving the source that is
+/// compiled.
+fn expand_test(wrapper: &(String, String), test: &Test) -> String {
+    let (ref prefix, ref suffix) = *wrapper;
+    format!("\n{}{}{}", prefix, create_test_input(&test.text), suffix)
+}
+
+/// Computes how lines of the generated test map back to the markdown.
+///
+/// Takes the template code preceding the example, and returns the number
+/// of lines it spans and the column offset of each example line.
+fn create_line_map(prefix: &str, lines: &[String]) -> (usize, Vec<isize>) {
+    // The test source starts with an empty line followed by the template
+    let prefix_lines = 1 + prefix.matches('\n').count();
+    let prefix_columns = prefix.rsplit('\n').next().unwrap_or("").chars().count() as isize;
+
+    let mut offsets = lines
+        .iter()
+        .map(|line| {
+            let stripped = line.chars().count() - clean_omitted_line(line).chars().count();
+            stripped as isize
+        })
+        .collect::<Vec<_>>();
+    if let Some(first) = offsets.first_mut() {
+        *first -= prefix_columns;
+    }
+    (prefix_lines, offsets)
+}
+
+/// Builds the same check for a test as `create_test_runner` generates.
+fn create_example(
+    config: &Config,
+    wrapper: &(String, String),
+    test: &Test,
+    module: &[String],
+    source_path: &Path,
+) -> Example {
+    let text = expand_test(wrapper, test);
+    let (prefix_lines, column_offsets) = create_line_map(&wrapper.0, &test.text);
+
+    let mut snippet = rt::Snippet::new(
+        &config.root_dir,
+        &config.out_dir,
+        &config.target_triple,
+        &text,
+    );
+    snippet
+        .source(
+            &source_path.to_string_lossy(),
+            test.line,
+            prefix_lines,
+            &column_offsets,
+        )
+        .name(&test.name);
+    if let Some(ref dir) = config.target_dir {
+        snippet.target_dir(dir);
+    }
+    if let Some(ref edition) = test.edition {
+        snippet.edition(edition);
+    }
+    if let Some(ref output) = test.expected_output {
+        snippet.expected_output(output);
+    }
+    let args = test.args.iter().map(String::as_str).collect::<Vec<_>>();
+    snippet.args(&args);
+    for (key, value) in &test.env {
+        snippet.env(key, value);
+    }
+    if let Some(ref input) = test.stdin {
+        snippet.stdin(input);
+    }
+    if let Some(timeout) = test.timeout.or(config.timeout) {
+        snippet.timeout(timeout);
+    }
+    if let Some(code) = test.exit_code {
+        snippet.exit_code(code);
+    }
+    if test.should_panic {
+        snippet.should_panic();
+    }
+
+    let check = if test.compile_fail {
+        Check::CompileFail(test.error_codes.clone())
+    } else if test.no_run {
+        Check::Compile
+    } else {
+        Check::Run
+    };
+    let path = module
+        .iter()
+        .chain(Some(&test.name))
+        .cloned()
+        .collect::<Vec<_>>()
+        .join("::");
+    Example {
+        name: test.name.clone(),
+        path,
+        ignore: test.ignore,
+        check,
+        snippet,
+    }
+}
+
 fn create_test_runner(
     config: &Config,
-    template: &Option<String>,
+    wrapper: &(String, String),
     test: &Test,
+    source_path: &Path,
+    batch: Option<(&Path, usize)>,
 ) -> Result<String, IoError> {
-    let template = template.clone().unwrap_or_else(|| String::from("{}"));
-    let test_text = create_test_input(&test.text);
+    let (prefix_lines, column_offsets) = create_line_map(&wrapper.0, &test.text);
 
     let mut s: Vec<u8> = Vec::new();
     if test.ignore {
         writeln!(s, "#[ignore]")?;
     }
-    if test.should_panic {
-        writeln!(s, "#[should_panic]")?;
-    }
 
     writeln!(s, "#[test] fn {}() {{", test.name)?;
     writeln!(
         s,
-        "    let s = &format!(r####\"\n{}\"####, r####\"{}\"####);",
-        template, test_text
+        "    let s = {};",
+        string_literal(&expand_test(wrapper, test))
     )?;
 
-    // if we are not running, just compile the test without running it
-    if test.no_run {
+    writeln!(
+        s,
+        "    __skeptic::rt::Snippet::new({}, {}, {:?}, s)",
+        path_expr(&config.root_dir),
+        path_expr(&config.out_dir),
+        config.target_triple
+    )?;
+    writeln!(
+        s,
+        "        .source({:?}, {}, {}, &{:?})",
+        source_path.to_string_lossy(),
+        test.line,
+        prefix_lines,
+        column_offsets
+    )?;
+    writeln!(s, "        .name({:?})", test.name)?;
+    if let Some(ref dir) = config.target_dir {
+        writeln!(s, "        .target_dir({})", path_expr(dir))?;
+    }
+    if let Some(ref edition) = test.edition {
+        writeln!(s, "        .edition({:?})", edition)?;
+    }
+    if let Some(ref output) = test.expected_output {
+        writeln!(s, "        .expected_output({})", string_literal(output))?;
+    }
+    if !test.args.is_empty() {
+        writeln!(s, "        .args(&{:?})", test.args)?;
+    }
+    for (key, value) in &test.env {
+        writeln!(s, "        .env({:?}, {:?})", key, value)?;
+    }
+    if let Some(ref input) = test.stdin {
+        writeln!(s, "        .stdin({})", string_literal(input))?;
+    }
+    if let Some(code) = test.exit_code {
+        writeln!(s, "        .exit_code({})", code)?;
+    }
+    if test.should_panic {
+        writeln!(s, "        .should_panic()")?;
+    }
+    if let Some(timeout) = test.timeout.or(config.timeout) {
         writeln!(
             s,
-            "    skeptic::rt::compile_test(r#\"{}\"#, r#\"{}\"#, r#\"{}\"#, s);",
-            config.root_dir.to_str().unwrap(),
-            config.out_dir.to_str().unwrap(),
-            config.target_triple
+            "        .timeout(::std::time::Duration::from_millis({}))",
+            timeout.as_millis()
         )?;
-    } else {
+    }
+    if let Some((path, line_offset)) = batch {
         writeln!(
             s,
-            "    skeptic::rt::run_test(r#\"{}\"#, r#\"{}\"#, r#\"{}\"#, s);",
-            config.root_dir.to_str().unwrap(),
-            config.out_dir.to_str().unwrap(),
-            config.target_triple
+            "        .batch({}, {:?}, {})",
+            path_expr(path),
+            test.name,
+            line_offset
         )?;
     }
+    if test.compile_fail {
+        writeln!(s, "        .compile_fail(&{:?});", test.error_codes)?;
+    } else if test.no_run {
+        // if we are not running, just compile the test without running it
+        writeln!(s, "        .compile();")?;
+    } else {
+        writeln!(s, "        .run();")?;
+    }
 
     writeln!(s, "}}")?;
     writeln!(s)?;
@@ -483,6 +2330,56 @@ fn create_test_runner(
     Ok(String::from_utf8(s).unwrap())
 }
 
+/// Returns a Rust string literal for `s`, raw so that the source of examples
+/// stays readable in the generated tests.
+///
+/// The literal is delimited by one more `#` than `s` ever has after a
+/// quote, so no text can end it early. Raw strings can't contain carriage
+/// returns, so text with those is escaped instead.
+fn string_literal(s: &str) -> String {
+    if s.contains('\r') {
+        return format!("{:?}", s);
+    }
+    let longest = s
+        .split('"')
+        .skip(1)
+        .map(|after_quote| after_quote.len() - after_quote.trim_start_matches('#').len())
+        .max()
+        .unwrap_or(0);
+    let hashes = "#".repeat(longest + 1);
+    format!("r{0}\"{1}\"{0}", hashes, s)
+}
+
+/// Writes a path as a Rust expression for the generated tests, keeping
+/// paths that aren't valid UTF-8 intact.
+fn path_expr(path: &Path) -> String {
+    match path.to_str() {
+        Some(path) => format!("{:?}", path),
+        None => non_utf8_path_expr(path),
+    }
+}
+
+#[cfg(unix)]
+fn non_utf8_path_expr(path: &Path) -> String {
+    use std::os::unix::ffi::OsStrExt;
+    format!(
+        "__skeptic::rt::path_from_bytes(&{:?})",
+        path.as_os_str().as_bytes()
+    )
+}
+
+#[cfg(windows)]
+fn non_utf8_path_expr(path: &Path) -> String {
+    use std::os::windows::ffi::OsStrExt;
+    let wide = path.as_os_str().encode_wide().collect::<Vec<_>>();
+    format!("__skeptic::rt::path_from_wide(&{:?})", wide)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn non_utf8_path_expr(path: &Path) -> String {
+    format!("{:?}", path.to_string_lossy())
+}
+
 fn write_if_contents_changed(name: &Path, contents: &str) -> Result<(), IoError> {
     // Can't open in write mode now as that would modify the last changed timestamp of the file
     match File::open(name) {
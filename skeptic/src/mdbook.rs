@@ -0,0 +1,254 @@
+//! Support for mdBook sources: finding the chapters of a book and expanding
+//! the `{{#include}}` directives of its preprocessor.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use pulldown_cmark::{Event, Parser, Tag};
+
+use crate::Error;
+
+// mdBook gives up on includes nested deeper than this
+const MAX_INCLUDE_DEPTH: usize = 10;
+
+/// Returns the source directory of the book in `book_dir`, as configured by
+/// its `book.toml`.
+pub(crate) fn source_dir(book_dir: &Path) -> Result<PathBuf, Error> {
+    let config_path = book_dir.join("book.toml");
+    let config = fs::read_to_string(&config_path)
+        .map_err(|err| book_error(&config_path, &err.to_string()))?;
+    let config = config
+        .parse::<toml::Value>()
+        .map_err(|err| book_error(&config_path, &err.to_string()))?;
+    let src = match config.get("book").and_then(|book| book.get("src")) {
+        Some(src) => src
+            .as_str()
+            .ok_or_else(|| book_error(&config_path, "`book.src` must be a string"))?,
+        None => "src",
+    };
+    Ok(book_dir.join(src))
+}
+
+/// Returns the chapters listed in the `SUMMARY.md` of a book's source
+/// directory, in order and skipping drafts.
+pub(crate) fn chapters(src_dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    let summary_path = src_dir.join("SUMMARY.md");
+    let summary = fs::read_to_string(&summary_path)
+        .map_err(|err| book_error(&summary_path, &err.to_string()))?;
+
+    let mut chapters = Vec::new();
+    for event in Parser::new(&summary) {
+        if let Event::Start(Tag::Link(_, ref dest, _)) = event {
+            // Draft chapters have no link
+            if dest.is_empty() || dest.contains("://") {
+                continue;
+            }
+            // Links may point to a heading within the chapter
+            let dest = dest.split('#').next().unwrap_or("");
+            let chapter = src_dir.join(dest);
+            if !dest.is_empty() && !chapters.contains(&chapter) {
+                chapters.push(chapter);
+            }
+        }
+    }
+    Ok(chapters)
+}
+
+/// A chapter with its include directives expanded.
+pub(crate) struct Expansion {
+    pub(crate) text: String,
+    /// The chapter line each line of the expanded text comes from. Included
+    /// lines come from the line of their directive.
+    pub(crate) lines: Vec<usize>,
+    /// The files that were included.
+    pub(crate) included: Vec<PathBuf>,
+}
+
+impl Expansion {
+    fn new() -> Expansion {
+        Expansion {
+            text: String::new(),
+            lines: vec![0],
+            included: Vec::new(),
+        }
+    }
+
+    fn push_original(&mut self, text: &str) {
+        for ch in text.chars() {
+            self.text.push(ch);
+            if ch == '\n' {
+                let line = self.lines.last().unwrap() + 1;
+                self.lines.push(line);
+            }
+        }
+    }
+
+    fn push_included(&mut self, text: &str) {
+        let line = *self.lines.last().unwrap();
+        self.text.push_str(text);
+        self.lines
+            .extend(std::iter::repeat_n(line, text.matches('\n').count()));
+    }
+}
+
+/// Expands the `{{#include}}` and `{{#rustdoc_include}}` directives of a
+/// chapter, resolving paths relative to `dir`.
+pub(crate) fn expand_includes(text: &str, dir: &Path) -> Result<Expansion, Error> {
+    expand(text, dir, 0)
+}
+
+fn expand(text: &str, dir: &Path, depth: usize) -> Result<Expansion, Error> {
+    let mut out = Expansion::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("{{#") {
+        let end = match rest[start..].find("}}") {
+            Some(end) => start + end + 2,
+            None => break,
+        };
+        // A backslash keeps the directive as it is, minus the backslash
+        if rest[..start].ends_with('\\') {
+            out.push_original(&rest[..start - 1]);
+            out.push_original(&rest[start..end]);
+            rest = &rest[end..];
+            continue;
+        }
+        out.push_original(&rest[..start]);
+
+        let directive = &rest[start + 3..end - 2];
+        let mut parts = directive.trim().splitn(2, char::is_whitespace);
+        let kind = parts.next().unwrap_or("");
+        let arg = parts.next().unwrap_or("").trim();
+        match kind {
+            "include" | "rustdoc_include" if depth < MAX_INCLUDE_DEPTH => {
+                let (file, selection) = parse_include(arg);
+                let path = dir.join(file);
+                let contents = fs::read_to_string(&path)
+                    .map_err(|err| book_error(&path, &format!("failed to include it: {}", err)))?;
+                let selected = if kind == "include" {
+                    select_lines(&contents, &selection, &path)?
+                } else {
+                    hide_unselected_lines(&contents, &selection, &path)?
+                };
+                let nested = expand(&selected, path.parent().unwrap_or(dir), depth + 1)?;
+                out.push_included(&nested.text);
+                out.included.push(path);
+                out.included.extend(nested.included);
+            }
+            // Other directives, like `{{#playground}}`, are left alone
+            _ => out.push_original(&rest[start..end]),
+        }
+        rest = &rest[end..];
+    }
+    out.push_original(rest);
+    Ok(out)
+}
+
+/// Which lines of an included file to use.
+#[derive(Debug)]
+enum Selection {
+    All,
+    /// A one-based, inclusive range of lines; open ends are `None`.
+    Lines(Option<usize>, Option<usize>),
+    Anchor(String),
+}
+
+fn parse_include(arg: &str) -> (&str, Selection) {
+    let mut parts = arg.splitn(3, ':');
+    let file = parts.next().unwrap_or("");
+    let first = parts.next();
+    let second = parts.next();
+    let number = |s: &str| s.trim().parse::<usize>().ok();
+    let selection = match (first, second) {
+        (None, _) => Selection::All,
+        (Some(first), None) => match number(first) {
+            Some(line) => Selection::Lines(Some(line), Some(line)),
+            None if first.trim().is_empty() => Selection::All,
+            None => Selection::Anchor(first.trim().to_owned()),
+        },
+        (Some(first), Some(second)) => Selection::Lines(number(first), number(second)),
+    };
+    (file, selection)
+}
+
+fn is_anchor_line(line: &str) -> bool {
+    line.contains("ANCHOR:") || line.contains("ANCHOR_END:")
+}
+
+fn anchor_name<'a>(line: &'a str, marker: &str) -> Option<&'a str> {
+    let start = line.find(marker)? + marker.len();
+    line[start..].split_whitespace().next()
+}
+
+/// Marks each line of `contents` with whether it is selected. Like mdBook,
+/// anchor lines are left out when selecting an anchor, and kept otherwise.
+fn mark_lines<'a>(
+    contents: &'a str,
+    selection: &Selection,
+    path: &Path,
+) -> Result<Vec<(&'a str, bool)>, Error> {
+    let mut marked = Vec::new();
+    let mut in_anchor = false;
+    let mut found_anchor = false;
+    for (index, line) in contents.lines().enumerate() {
+        if let Selection::Anchor(ref name) = *selection {
+            if is_anchor_line(line) {
+                if anchor_name(line, "ANCHOR:") == Some(name.as_str()) {
+                    in_anchor = true;
+                    found_anchor = true;
+                } else if anchor_name(line, "ANCHOR_END:") == Some(name.as_str()) {
+                    in_anchor = false;
+                }
+                continue;
+            }
+        }
+        let selected = match *selection {
+            Selection::All => true,
+            Selection::Lines(first, last) => {
+                let line_number = index + 1;
+                first.is_none_or(|first| line_number >= first)
+                    && last.is_none_or(|last| line_number <= last)
+            }
+            Selection::Anchor(_) => in_anchor,
+        };
+        marked.push((line, selected));
+    }
+    if let Selection::Anchor(ref name) = *selection {
+        if !found_anchor {
+            return Err(book_error(path, &format!("anchor `{}` not found", name)));
+        }
+    }
+    Ok(marked)
+}
+
+fn select_lines(contents: &str, selection: &Selection, path: &Path) -> Result<String, Error> {
+    let lines = mark_lines(contents, selection, path)?
+        .into_iter()
+        .filter(|&(_, selected)| selected)
+        .map(|(line, _)| line)
+        .collect::<Vec<_>>();
+    Ok(lines.join("\n"))
+}
+
+/// Includes every line, hiding the unselected ones from readers with the
+/// `# ` prefix rustdoc uses.
+fn hide_unselected_lines(
+    contents: &str,
+    selection: &Selection,
+    path: &Path,
+) -> Result<String, Error> {
+    let lines = mark_lines(contents, selection, path)?
+        .into_iter()
+        .map(|(line, selected)| {
+            if selected {
+                line.to_owned()
+            } else {
+                format!("# {}", line)
+            }
+        })
+        .collect::<Vec<_>>();
+    Ok(lines.join("\n"))
+}
+
+fn book_error(path: &Path, msg: &str) -> Error {
+    Error::Book(format!("{}: {}", path.display(), msg))
+}
@@ -1,135 +1,925 @@
+use std::collections::hash_map::DefaultHasher;
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::env;
-use std::ffi::OsStr;
-use std::fs;
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read, Write};
 use std::path::{Path, PathBuf};
-use std::process::Command;
-use std::time::SystemTime;
+use std::process::{Child, Command, ExitStatus, Output, Stdio};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
 
-use cargo_metadata::Edition;
-use error_chain::error_chain;
+use once_cell::sync::Lazy;
 use walkdir::WalkDir;
 
+use crate::Error;
+
 pub fn compile_test(root_dir: &str, out_dir: &str, target_triple: &str, test_text: &str) {
-    handle_test(
-        root_dir,
-        out_dir,
-        target_triple,
-        test_text,
-        CompileType::Check,
-    );
+    Snippet::new(root_dir, out_dir, target_triple, test_text).compile();
 }
 
 pub fn run_test(root_dir: &str, out_dir: &str, target_triple: &str, test_text: &str) {
-    handle_test(
-        root_dir,
-        out_dir,
-        target_triple,
-        test_text,
-        CompileType::Full,
-    );
-}
-
-fn handle_test(
-    root_dir: &str,
-    target_dir: &str,
-    target_triple: &str,
-    test_text: &str,
-    compile_type: CompileType,
-) {
-    let out_dir = tempfile::Builder::new()
-        .prefix("rust-skeptic")
-        .tempdir()
-        .unwrap();
-    let testcase_path = out_dir.path().join("test.rs");
-    fs::write(&testcase_path, test_text.as_bytes()).unwrap();
-
-    // OK, here's where a bunch of magic happens using assumptions
-    // about cargo internals. We are going to use rustc to compile
-    // the examples, but to do that we've got to tell it where to
-    // look for the rlibs with the -L flag, and what their names
-    // are with the --extern flag. This is going to involve
-    // parsing fingerprints out of the lockfile and looking them
-    // up in the fingerprint file.
-
-    let root_dir = PathBuf::from(root_dir);
-    let mut target_dir = PathBuf::from(target_dir);
-    target_dir.pop();
-    target_dir.pop();
-    target_dir.pop();
-    let mut deps_dir = target_dir.clone();
-    deps_dir.push("deps");
-
-    let rustc = env::var("RUSTC").unwrap_or_else(|_| String::from("rustc"));
-    let mut cmd = Command::new(rustc);
-    cmd.arg(testcase_path)
-        .arg("--verbose")
-        .arg("--crate-type=bin");
-
-    // Find the edition
-
-    // This has to come before "-L".
-    let metadata_path = root_dir.join("Cargo.toml");
-    let metadata = get_cargo_meta(&metadata_path).expect("failed to read Cargo.toml");
-    let edition = metadata
-        .packages
-        .iter()
-        .filter_map(|package| edition_str(&package.edition))
-        .max()
-        .unwrap();
-    if edition != "2015" {
-        cmd.arg(format!("--edition={}", edition));
-    }
-
-    cmd.arg("-L")
-        .arg(&target_dir)
-        .arg("-L")
-        .arg(&deps_dir)
-        .arg("--target")
-        .arg(target_triple);
-
-    for dep in get_rlib_dependencies(root_dir, target_dir).expect("failed to read dependencies") {
-        cmd.arg("--extern");
-        cmd.arg(format!(
-            "{}={}",
-            dep.libname,
-            dep.rlib.to_str().expect("filename not utf8"),
-        ));
-    }
-
-    let binary_path = out_dir.path().join("out.exe");
-    match compile_type {
-        CompileType::Full => cmd.arg("-o").arg(&binary_path),
-        CompileType::Check => cmd.arg(format!(
-            "--emit=dep-info={0}.d,metadata={0}.m",
-            binary_path.display()
-        )),
-    };
-
-    interpret_output(cmd);
-
-    if let CompileType::Check = compile_type {
-        return;
-    }
-
-    let mut cmd = Command::new(binary_path);
-    cmd.current_dir(out_dir.path());
-    interpret_output(cmd);
-}
-
-fn interpret_output(mut command: Command) {
-    let output = command.output().unwrap();
-    print!("{}", String::from_utf8(output.stdout).unwrap());
-    eprint!("{}", String::from_utf8(output.stderr).unwrap());
-    if !output.status.success() {
-        panic!("Command failed:\n{:?}", command);
+    Snippet::new(root_dir, out_dir, target_triple, test_text).run();
+}
+
+/// Rebuilds a path that isn't valid UTF-8 from its bytes.
+///
+/// Generated tests use this for paths they can't spell as string literals.
+#[cfg(unix)]
+pub fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(OsStr::from_bytes(bytes))
+}
+
+/// Rebuilds a path that isn't valid UTF-8 from its UTF-16 code units.
+///
+/// Generated tests use this for paths they can't spell as string literals.
+#[cfg(windows)]
+pub fn path_from_wide(wide: &[u16]) -> PathBuf {
+    use std::os::windows::ffi::OsStringExt;
+    PathBuf::from(OsString::from_wide(wide))
+}
+
+/// A code example that is compiled, and possibly run, as a test.
+///
+/// This is what the tests generated by skeptic call into. It is not meant
+/// to be used directly.
+#[derive(Clone, Debug)]
+pub struct Snippet {
+    root_dir: PathBuf,
+    out_dir: PathBuf,
+    target_dir: Option<PathBuf>,
+    target_triple: String,
+    text: String,
+    name: Option<String>,
+    source: Option<SourceMap>,
+    edition: Option<String>,
+    expected_output: Option<String>,
+    args: Vec<String>,
+    env: Vec<(String, String)>,
+    stdin: Option<String>,
+    timeout: Duration,
+    exit_code: i32,
+    should_panic: bool,
+    batch: Option<Batch>,
+}
+
+/// How long an example may run before it is killed, unless set with
+/// [`Snippet::timeout`].
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+// The exit code of a Rust program whose main thread panicked
+const PANIC_EXIT_CODE: i32 = 101;
+
+/// Environment variable telling a batch binary which example to run.
+pub const BATCH_TEST_VAR: &str = "SKEPTIC_BATCH_TEST";
+
+// How long a batch may take to compile before its lock is considered stale
+const STALE_LOCK_AGE: Duration = Duration::from_secs(600);
+
+#[derive(Clone, Debug)]
+struct Batch {
+    path: PathBuf,
+    module: String,
+    line_offset: usize,
+}
+
+// Maps lines of the compiled test back to the markdown they came from
+#[derive(Clone, Debug)]
+struct SourceMap {
+    file: String,
+    first_line: usize,
+    prefix_lines: usize,
+    column_offsets: Vec<isize>,
+}
+
+impl Snippet {
+    pub fn new<P, Q>(root_dir: P, out_dir: Q, target_triple: &str, text: &str) -> Snippet
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        Snippet {
+            root_dir: root_dir.as_ref().to_owned(),
+            out_dir: out_dir.as_ref().to_owned(),
+            target_dir: None,
+            target_triple: target_triple.to_owned(),
+            text: text.to_owned(),
+            name: None,
+            source: None,
+            edition: None,
+            expected_output: None,
+            args: Vec::new(),
+            env: Vec::new(),
+            stdin: None,
+            timeout: DEFAULT_TIMEOUT,
+            exit_code: 0,
+            should_panic: false,
+            batch: None,
+        }
+    }
+
+    /// Records where the example was taken from, so that diagnostics point
+    /// into the markdown file instead of the temporary test file.
+    ///
+    /// `first_line` is the markdown line of the first line of the example,
+    /// which starts after `prefix_lines` lines of template. Column `n` of
+    /// the `i`th example line maps to column `n + column_offsets[i]` in
+    /// the markdown, accounting for indentation added by the template and
+    /// for stripped `# ` prefixes.
+    pub fn source(
+        &mut self,
+        file: &str,
+        first_line: usize,
+        prefix_lines: usize,
+        column_offsets: &[isize],
+    ) -> &mut Snippet {
+        self.source = Some(SourceMap {
+            file: file.to_owned(),
+            first_line,
+            prefix_lines,
+            column_offsets: column_offsets.to_vec(),
+        });
+        self
+    }
+
+    /// Names the example, such as after its test.
+    ///
+    /// Builds are cached under the example's name, so that an edited
+    /// example replaces its previous build instead of being cached
+    /// alongside it. Unnamed examples are cached under their source.
+    pub fn name(&mut self, name: &str) -> &mut Snippet {
+        self.name = Some(name.to_owned());
+        self
+    }
+
+    /// Sets the directory the crate and its dependencies were built in,
+    /// such as `target/debug`.
+    ///
+    /// By default this is derived from the build script's output directory.
+    pub fn target_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Snippet {
+        self.target_dir = Some(dir.as_ref().to_owned());
+        self
+    }
+
+    /// Compiles the example for the given edition, such as `"2018"`.
+    ///
+    /// By default the newest edition used by any package of the crate's
+    /// workspace is chosen.
+    pub fn edition(&mut self, edition: &str) -> &mut Snippet {
+        self.edition = Some(edition.to_owned());
+        self
+    }
+
+    /// Makes the test fail unless the example prints `output` to stdout.
+    ///
+    /// Line endings and trailing whitespace are ignored in the comparison.
+    pub fn expected_output(&mut self, output: &str) -> &mut Snippet {
+        self.expected_output = Some(output.to_owned());
+        self
+    }
+
+    /// Passes `args` to the example's binary when it runs.
+    pub fn args(&mut self, args: &[&str]) -> &mut Snippet {
+        self.args.extend(args.iter().map(|arg| arg.to_string()));
+        self
+    }
+
+    /// Sets an environment variable for the example's binary when it runs.
+    ///
+    /// Other variables are inherited from the test.
+    pub fn env(&mut self, key: &str, value: &str) -> &mut Snippet {
+        self.env.push((key.to_owned(), value.to_owned()));
+        self
+    }
+
+    /// Feeds `input` to the example's stdin when it runs.
+    ///
+    /// By default the example's stdin is empty.
+    pub fn stdin(&mut self, input: &str) -> &mut Snippet {
+        self.stdin = Some(input.to_owned());
+        self
+    }
+
+    /// Kills the example and fails the test if it runs longer than
+    /// `timeout`.
+    ///
+    /// Defaults to [`DEFAULT_TIMEOUT`]. Compilation is not limited.
+    pub fn timeout(&mut self, timeout: Duration) -> &mut Snippet {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Makes the test fail unless the example exits with `code`.
+    ///
+    /// By default the example must exit successfully.
+    pub fn exit_code(&mut self, code: i32) -> &mut Snippet {
+        self.exit_code = code;
+        self
+    }
+
+    /// Makes the test fail unless the example panics when it runs.
+    ///
+    /// Examples that fail to compile, or exit with a code other than the
+    /// one of a panic, fail the test.
+    pub fn should_panic(&mut self) -> &mut Snippet {
+        self.should_panic = true;
+        self
+    }
+
+    /// Marks the example as part of a batch of examples compiled together.
+    ///
+    /// `path` is the batch's source file, where the example is wrapped in
+    /// module `module` and starts after line `line_offset`. When the batch
+    /// fails to compile, the example is compiled on its own instead.
+    pub fn batch<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        module: &str,
+        line_offset: usize,
+    ) -> &mut Snippet {
+        self.batch = Some(Batch {
+            path: path.as_ref().to_owned(),
+            module: module.to_owned(),
+            line_offset,
+        });
+        self
+    }
+
+    /// Compiles the example without running it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the example fails to compile.
+    pub fn compile(&self) {
+        finish(self.try_compile());
+    }
+
+    /// Compiles and runs the example.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the example fails to compile or to run.
+    pub fn run(&self) {
+        finish(self.try_run());
+    }
+
+    /// Checks that the example fails to compile.
+    ///
+    /// If any error codes are given, each of them must be reported by the
+    /// compiler as well.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the example compiles, or fails with other error codes.
+    pub fn compile_fail(&self, error_codes: &[&str]) {
+        finish(self.try_compile_fail(error_codes));
+    }
+
+    /// Like [`compile`](Snippet::compile), but returns an error instead of
+    /// panicking.
+    pub fn try_compile(&self) -> Result<(), Error> {
+        self.handle_test(CompileType::Check)
+    }
+
+    /// Like [`compile_fail`](Snippet::compile_fail), but returns an error
+    /// instead of panicking.
+    pub fn try_compile_fail(&self, error_codes: &[&str]) -> Result<(), Error> {
+        self.handle_test(CompileType::Fail(error_codes))
+    }
+
+    /// Like [`run`](Snippet::run), but returns an error instead of
+    /// panicking.
+    pub fn try_run(&self) -> Result<(), Error> {
+        if let Some(ref batch) = self.batch {
+            if let Some(binary_path) = self.batch_binary(batch) {
+                let out_dir = tempfile::Builder::new().prefix("rust-skeptic").tempdir()?;
+                let mut cmd = self.run_command(&binary_path, out_dir.path());
+                cmd.env(BATCH_TEST_VAR, &batch.module);
+                return self.check_run(cmd, &batch.path, batch.line_offset);
+            }
+        }
+        self.handle_test(CompileType::Full)
+    }
+
+    fn handle_test(&self, compile_type: CompileType) -> Result<(), Error> {
+        if let CompileType::Fail(error_codes) = compile_type {
+            return self.handle_compile_fail(error_codes);
+        }
+
+        let rustc = self.rustc_invocation()?;
+
+        // Successful builds are cached in the output directory, one entry
+        // per example, which is rebuilt when anything affecting the build
+        // changes. The source is kept next to the build so that locations
+        // in panic messages of a cached binary stay valid.
+        let cache_dir = self.out_dir.join("skeptic-cache").join(self.cache_entry());
+        let testcase_path = cache_dir.join("test.rs");
+        let binary_path = cache_dir.join("out.exe");
+        let checked_path = cache_dir.join("checked");
+        let key_path = cache_dir.join("key");
+        let key = rustc.key(&self.text);
+
+        let fresh = fs::read_to_string(&key_path).is_ok_and(|cached| cached == key);
+        let cached = fresh
+            && match compile_type {
+                CompileType::Full => binary_path.exists(),
+                _ => checked_path.exists() || binary_path.exists(),
+            };
+        if !cached {
+            fs::create_dir_all(&cache_dir)?;
+            if !fresh {
+                // The key is written last, so that an interrupted build
+                // isn't taken for a complete one
+                for path in &[&key_path, &binary_path, &checked_path] {
+                    if path.exists() {
+                        fs::remove_file(path)?;
+                    }
+                }
+            }
+            // Other tests may be compiling the same source concurrently, so
+            // build in a private directory and move the results into place
+            let build_dir = tempfile::Builder::new()
+                .prefix("rust-skeptic")
+                .tempdir_in(&cache_dir)?;
+            let mut source = tempfile::NamedTempFile::new_in(&cache_dir)?;
+            source.write_all(self.text.as_bytes())?;
+            source
+                .persist(&testcase_path)
+                .map_err(|err| Error::Io(err.error))?;
+
+            let mut cmd = rustc.command();
+            cmd.arg(&testcase_path);
+            let build_path = build_dir.path().join("out.exe");
+            match compile_type {
+                CompileType::Full => cmd.arg("-o").arg(&build_path),
+                _ => cmd.arg(emit_metadata(&build_path)),
+            };
+            self.interpret_output(cmd, &Limits::none(), &testcase_path, 0, Error::Compile)?;
+
+            match compile_type {
+                CompileType::Full => fs::rename(&build_path, &binary_path)?,
+                _ => fs::write(&checked_path, "")?,
+            }
+            fs::write(&key_path, &key)?;
+        }
+
+        if let CompileType::Check = compile_type {
+            return Ok(());
+        }
+
+        let out_dir = tempfile::Builder::new().prefix("rust-skeptic").tempdir()?;
+        let cmd = self.run_command(&binary_path, out_dir.path());
+        self.check_run(cmd, &testcase_path, 0)
+    }
+
+    fn handle_compile_fail(&self, error_codes: &[&str]) -> Result<(), Error> {
+        let out_dir = tempfile::Builder::new().prefix("rust-skeptic").tempdir()?;
+        let testcase_path = out_dir.path().join("test.rs");
+        fs::write(&testcase_path, self.text.as_bytes())?;
+
+        let mut cmd = self.rustc_invocation()?.command();
+        cmd.arg(&testcase_path)
+            .arg(emit_metadata(&out_dir.path().join("out.exe")));
+
+        let (status, _, stderr) = self.execute(&mut cmd, &Limits::none(), &testcase_path, 0)?;
+        if status.success() {
+            return Err(Error::Compile(String::from(
+                "Example compiled successfully but was expected to fail",
+            )));
+        }
+        let missing = error_codes
+            .iter()
+            .filter(|code| !stderr.contains(&format!("error[{}]", code)))
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            return Err(Error::Compile(format!(
+                "Example failed to compile, but not with the expected error codes: {:?}",
+                missing
+            )));
+        }
+        Ok(())
+    }
+
+    // Names the cache entry of the example
+    fn cache_entry(&self) -> String {
+        let mut hasher = DefaultHasher::new();
+        match self.name {
+            Some(ref name) => {
+                // Names are only unique within a document
+                self.source
+                    .as_ref()
+                    .map(|source| &source.file)
+                    .hash(&mut hasher);
+                format!("{}-{:016x}", name, hasher.finish())
+            }
+            None => {
+                self.text.hash(&mut hasher);
+                format!("{:016x}", hasher.finish())
+            }
+        }
+    }
+
+    // Builds the rustc invocation for the example, without input and
+    // output files
+    fn rustc_invocation(&self) -> Result<RustcInvocation, Error> {
+        // OK, here's where a bunch of magic happens using assumptions
+        // about cargo internals. We are going to use rustc to compile
+        // the examples, but to do that we've got to tell it where to
+        // look for the rlibs with the -L flag, and what their names
+        // are with the --extern flag. This is going to involve
+        // parsing fingerprints out of the lockfile and looking them
+        // up in the fingerprint file.
+
+        let root_dir = self.root_dir.clone();
+        let target_dir = self.target_dir.clone().unwrap_or_else(|| {
+            let mut target_dir = self.out_dir.clone();
+            target_dir.pop();
+            target_dir.pop();
+            target_dir.pop();
+            target_dir
+        });
+        let mut deps_dir = target_dir.clone();
+        deps_dir.push("deps");
+
+        let mut args = vec![
+            OsString::from("--verbose"),
+            OsString::from("--crate-type=bin"),
+        ];
+
+        // Find the edition
+
+        // This has to come before "-L".
+        let edition = match self.edition {
+            Some(ref edition) => edition.clone(),
+            None => {
+                let metadata_path = root_dir.join("Cargo.toml");
+                let metadata = get_cargo_meta(&metadata_path)?;
+                metadata
+                    .packages
+                    .iter()
+                    .map(|package| package.edition.as_str())
+                    .max()
+                    .unwrap_or("2015")
+                    .to_owned()
+            }
+        };
+        if edition != "2015" {
+            args.push(OsString::from(format!("--edition={}", edition)));
+        }
+
+        args.push(OsString::from("-L"));
+        args.push(OsString::from(&target_dir));
+        args.push(OsString::from("-L"));
+        args.push(OsString::from(deps_dir));
+        args.push(OsString::from("--target"));
+        args.push(OsString::from(&self.target_triple));
+
+        let deps = get_rlib_dependencies(root_dir, target_dir)?;
+        for dep in &deps {
+            let mut arg = OsString::from(format!("{}=", dep.libname));
+            arg.push(&dep.rlib);
+            args.push(OsString::from("--extern"));
+            args.push(arg);
+        }
+
+        Ok(RustcInvocation { args, deps })
+    }
+
+    // Builds the command running the example's binary in `dir`
+    fn run_command(&self, binary_path: &Path, dir: &Path) -> Command {
+        let mut cmd = Command::new(binary_path);
+        cmd.current_dir(dir).args(&self.args);
+        for (key, value) in &self.env {
+            cmd.env(key, value);
+        }
+        cmd
+    }
+
+    // Runs the compiled example and checks its output
+    fn check_run(
+        &self,
+        cmd: Command,
+        testcase_path: &Path,
+        line_offset: usize,
+    ) -> Result<(), Error> {
+        let limits = Limits {
+            input: self.stdin.as_deref(),
+            timeout: Some(self.timeout),
+        };
+        let mut cmd = cmd;
+        let (status, stdout, _) = self.execute(&mut cmd, &limits, testcase_path, line_offset)?;
+        self.check_exit_status(status, &cmd)?;
+
+        if let Some(ref expected) = self.expected_output {
+            let expected = normalize_output(expected);
+            let actual = normalize_output(&stdout);
+            if expected != actual {
+                return Err(Error::Run(format!(
+                    "Output did not match the expected output:\n{}",
+                    diff_lines(&expected, &actual)
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    // Checks that the example exited the way it was expected to
+    fn check_exit_status(&self, status: ExitStatus, command: &Command) -> Result<(), Error> {
+        let expected = if self.should_panic {
+            PANIC_EXIT_CODE
+        } else {
+            self.exit_code
+        };
+        if status.code() == Some(expected) {
+            return Ok(());
+        }
+        let actual = match status.code() {
+            Some(code) => format!("exited with code {}", code),
+            None => String::from("was terminated by a signal"),
+        };
+        let msg = if self.should_panic && status.success() {
+            String::from("Example succeeded but was expected to panic")
+        } else if self.should_panic {
+            format!("Example was expected to panic, but {}", actual)
+        } else if expected != 0 {
+            format!(
+                "Example was expected to exit with code {}, but {}",
+                expected, actual
+            )
+        } else {
+            format!("Command failed:\n{:?}", command)
+        };
+        Err(Error::Run(msg))
+    }
+
+    // Runs the command and returns its stdout, or the error built by
+    // `failure` if it doesn't succeed
+    fn interpret_output(
+        &self,
+        mut command: Command,
+        limits: &Limits,
+        testcase_path: &Path,
+        line_offset: usize,
+        failure: fn(String) -> Error,
+    ) -> Result<String, Error> {
+        let (status, stdout, _) = self.execute(&mut command, limits, testcase_path, line_offset)?;
+        if !status.success() {
+            return Err(failure(format!("Command failed:\n{:?}", command)));
+        }
+        Ok(stdout)
+    }
+
+    // Runs the command within `limits`, forwarding its output with
+    // locations remapped, and returns its exit status along with stdout and
+    // stderr
+    fn execute(
+        &self,
+        command: &mut Command,
+        limits: &Limits,
+        testcase_path: &Path,
+        line_offset: usize,
+    ) -> Result<(ExitStatus, String, String), Error> {
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        // The pipes are serviced from other threads, so that a child
+        // filling one of them while the others are unattended doesn't
+        // deadlock
+        let writer = child.stdin.take().map(|mut stdin| {
+            let input = limits.input.unwrap_or("").to_owned();
+            thread::spawn(move || {
+                // The child may exit without reading its input
+                let _ = stdin.write_all(input.as_bytes());
+            })
+        });
+        let stdout_reader = child.stdout.take().map(read_in_background);
+        let stderr_reader = child.stderr.take().map(read_in_background);
+
+        let status = match limits.timeout {
+            Some(timeout) => match wait_timeout(&mut child, timeout)? {
+                Some(status) => status,
+                None => {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return Err(Error::Run(format!(
+                        "Example timed out after {}s",
+                        timeout.as_secs_f64()
+                    )));
+                }
+            },
+            None => child.wait()?,
+        };
+        if let Some(writer) = writer {
+            let _ = writer.join();
+        }
+        let output = Output {
+            status,
+            stdout: stdout_reader.map_or_else(Vec::new, join_reader),
+            stderr: stderr_reader.map_or_else(Vec::new, join_reader),
+        };
+        let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+        let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+        let testcase_path = testcase_path.to_string_lossy();
+        print!(
+            "{}",
+            self.remap_diagnostics(&stdout, &testcase_path, line_offset)
+        );
+        eprint!(
+            "{}",
+            self.remap_diagnostics(&stderr, &testcase_path, line_offset)
+        );
+        Ok((output.status, stdout, stderr))
+    }
+
+    // Rewrites `<testcase_path>:line:column` locations in compiler and panic
+    // messages to point into the markdown file. Locations inside the template
+    // are left as they are. The example's source starts `line_offset` lines
+    // into the compiled file.
+    pub(crate) fn remap_diagnostics(
+        &self,
+        text: &str,
+        testcase_path: &str,
+        line_offset: usize,
+    ) -> String {
+        match self.source {
+            Some(ref source) => source.remap(text, testcase_path, line_offset),
+            None => text.to_owned(),
+        }
+    }
+
+    // Returns the batch binary containing this example, compiling it if
+    // no other test has done so yet, or `None` if the batch doesn't build
+    fn batch_binary(&self, batch: &Batch) -> Option<PathBuf> {
+        let source = fs::read_to_string(&batch.path).ok()?;
+        let rustc = self.rustc_invocation().ok()?;
+        let key = rustc.key(&source);
+
+        // Each batch file has a single cache entry, rebuilt when its key
+        // changes
+        let dir = self
+            .out_dir
+            .join("skeptic-batch")
+            .join(batch.path.file_stem()?);
+        let binary_path = dir.join("out.exe");
+        let failed_path = dir.join("failed");
+        let key_path = dir.join("key");
+        let lock_path = dir.join("lock");
+        fs::create_dir_all(&dir).ok()?;
+
+        // Tests run concurrently, possibly in separate processes, so the
+        // first one to take the lock compiles while the others wait
+        let mut locked = false;
+        while !locked {
+            if fs::read_to_string(&key_path).is_ok_and(|cached| cached == key) {
+                if binary_path.exists() {
+                    return Some(binary_path);
+                }
+                if failed_path.exists() {
+                    return None;
+                }
+            }
+            match OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&lock_path)
+            {
+                Ok(_) => locked = true,
+                Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                    // A test that died while compiling leaves its lock behind
+                    let stale = fs::metadata(&lock_path)
+                        .and_then(|meta| meta.modified())
+                        .ok()
+                        .and_then(|modified| modified.elapsed().ok())
+                        .is_some_and(|age| age > STALE_LOCK_AGE);
+                    if stale {
+                        let _ = fs::remove_file(&lock_path);
+                    } else {
+                        thread::sleep(Duration::from_millis(100));
+                    }
+                }
+                Err(_) => return None,
+            }
+        }
+
+        // Another test may have built the batch while this one waited
+        if fs::read_to_string(&key_path).is_ok_and(|cached| cached == key) {
+            let _ = fs::remove_file(&lock_path);
+            return Some(binary_path).filter(|path| path.exists());
+        }
+        for path in &[&key_path, &binary_path, &failed_path] {
+            let _ = fs::remove_file(path);
+        }
+
+        let tmp_path = dir.join("out.exe.tmp");
+        let mut cmd = rustc.command();
+        cmd.arg(&batch.path).arg("-o").arg(&tmp_path);
+        let built = cmd.output().is_ok_and(|output| output.status.success());
+        let recorded = if built {
+            fs::rename(&tmp_path, &binary_path)
+        } else {
+            fs::write(&failed_path, "")
+        };
+        if recorded.is_ok() {
+            let _ = fs::write(&key_path, &key);
+        }
+        let _ = fs::remove_file(&lock_path);
+        if built {
+            Some(binary_path)
+        } else {
+            None
+        }
+    }
+}
+
+// What a command is given to run, and how long
+struct Limits<'a> {
+    input: Option<&'a str>,
+    timeout: Option<Duration>,
+}
+
+impl<'a> Limits<'a> {
+    fn none() -> Limits<'a> {
+        Limits {
+            input: None,
+            timeout: None,
+        }
+    }
+}
+
+fn read_in_background<R: Read + Send + 'static>(mut pipe: R) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        let _ = pipe.read_to_end(&mut buf);
+        buf
+    })
+}
+
+fn join_reader(reader: JoinHandle<Vec<u8>>) -> Vec<u8> {
+    reader.join().unwrap_or_default()
+}
+
+// Waits for the child to exit, returning `None` if it doesn't within
+// `timeout`
+fn wait_timeout(child: &mut Child, timeout: Duration) -> io::Result<Option<ExitStatus>> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(None);
+        }
+        thread::sleep((deadline - now).min(Duration::from_millis(10)));
     }
 }
 
+impl SourceMap {
+    fn remap(&self, text: &str, testcase_path: &str, line_offset: usize) -> String {
+        let mut out = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(pos) = rest.find(testcase_path) {
+            out.push_str(&rest[..pos]);
+            rest = &rest[pos + testcase_path.len()..];
+
+            let location = parse_location(rest).and_then(|(line, column, len)| {
+                let index = line.checked_sub(line_offset + self.prefix_lines + 1)?;
+                let offset = *self.column_offsets.get(index)?;
+                let column = (column as isize + offset).max(1);
+                Some((self.first_line + index, column, len))
+            });
+            match location {
+                Some((line, column, len)) => {
+                    out.push_str(&format!("{}:{}:{}", self.file, line, column));
+                    rest = &rest[len..];
+                }
+                None => out.push_str(testcase_path),
+            }
+        }
+        out.push_str(rest);
+        out
+    }
+}
+
+// Turns the outcome of a test into a panic for the test harness
+fn finish(result: Result<(), Error>) {
+    if let Err(err) = result {
+        panic!("{}", err);
+    }
+}
+
+fn rustc() -> String {
+    env::var("RUSTC").unwrap_or_else(|_| String::from("rustc"))
+}
+
+// The output of `rustc -vV`, which identifies the compiler
+static RUSTC_VERSION: Lazy<Vec<u8>> = Lazy::new(|| {
+    Command::new(rustc())
+        .arg("-vV")
+        .output()
+        .map(|output| output.stdout)
+        .unwrap_or_default()
+});
+
+// A rustc invocation compiling examples, without its input and output
+// files
+struct RustcInvocation {
+    args: Vec<OsString>,
+    deps: Vec<Fingerprint>,
+}
+
+impl RustcInvocation {
+    fn command(&self) -> Command {
+        let mut cmd = Command::new(rustc());
+        cmd.args(&self.args);
+        cmd
+    }
+
+    // Identifies a build of `source` by the compiler, its arguments, which
+    // include the paths of the dependencies, and the state of those
+    fn key(&self, source: &str) -> String {
+        let mut hasher = DefaultHasher::new();
+        source.hash(&mut hasher);
+        rustc().hash(&mut hasher);
+        RUSTC_VERSION.hash(&mut hasher);
+        self.args.hash(&mut hasher);
+        for dep in &self.deps {
+            fs::metadata(&dep.rlib)
+                .and_then(|meta| meta.modified())
+                .ok()
+                .hash(&mut hasher);
+        }
+        format!("{:016x}", hasher.finish())
+    }
+}
+
+fn normalize_output(output: &str) -> String {
+    let lines = output.lines().map(str::trim_end).collect::<Vec<_>>();
+    lines.join("\n").trim_end().to_owned()
+}
+
+// Renders a line based diff, marking expected lines with `-` and actual
+// lines with `+`
+pub(crate) fn diff_lines(expected: &str, actual: &str) -> String {
+    let expected = expected.lines().collect::<Vec<_>>();
+    let actual = actual.lines().collect::<Vec<_>>();
+
+    // Longest common subsequence lengths of all suffixes
+    let mut lcs = vec![vec![0; actual.len() + 1]; expected.len() + 1];
+    for i in (0..expected.len()).rev() {
+        for j in (0..actual.len()).rev() {
+            lcs[i][j] = if expected[i] == actual[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut out = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < expected.len() || j < actual.len() {
+        if i < expected.len() && j < actual.len() && expected[i] == actual[j] {
+            out.push_str(&format!(" {}\n", expected[i]));
+            i += 1;
+            j += 1;
+        } else if i < expected.len() && (j == actual.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            out.push_str(&format!("-{}\n", expected[i]));
+            i += 1;
+        } else {
+            out.push_str(&format!("+{}\n", actual[j]));
+            j += 1;
+        }
+    }
+    out
+}
+
+// Parses a `:line:column` suffix, returning both numbers and its length
+fn parse_location(s: &str) -> Option<(usize, usize, usize)> {
+    let mut len = 0;
+    let mut numbers = [0; 2];
+    for number in numbers.iter_mut() {
+        let digits = s[len..].strip_prefix(':')?;
+        let end = digits
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(digits.len());
+        *number = digits[..end].parse().ok()?;
+        len += 1 + end;
+    }
+    Some((numbers[0], numbers[1], len))
+}
+
 // Retrieve the exact dependencies for a given build by
 // cross-referencing the lockfile with the fingerprint file
-fn get_rlib_dependencies(root_dir: PathBuf, target_dir: PathBuf) -> Result<Vec<Fingerprint>> {
+fn get_rlib_dependencies(
+    root_dir: PathBuf,
+    target_dir: PathBuf,
+) -> Result<Vec<Fingerprint>, Error> {
     let lock = LockedDeps::from_path(root_dir).or_else(|_| {
         // could not find Cargo.lock in $CARGO_MAINFEST_DIR
         // try relative to target_dir
@@ -141,64 +931,90 @@ fn get_rlib_dependencies(root_dir: PathBuf, target_dir: PathBuf) -> Result<Vec<F
 
     let fingerprint_dir = target_dir.join(".fingerprint/");
     let locked_deps: HashMap<String, String> = lock.collect();
-    let mut found_deps: HashMap<String, Fingerprint> = HashMap::new();
-
-    for finger in WalkDir::new(fingerprint_dir)
+    let fingerprints = WalkDir::new(fingerprint_dir)
         .into_iter()
-        .filter_map(|v| Fingerprint::from_path(v.ok()?.path()).ok())
-    {
+        .filter_map(|v| Fingerprint::from_path(v.ok()?.path()).ok());
+    let found_deps = select_fingerprints(&locked_deps, fingerprints);
+
+    // Sorted so that the same crate produces the same rustc invocation
+    let mut deps = found_deps
+        .into_values()
+        .filter(|val| val.rlib.exists())
+        .collect::<Vec<_>>();
+    deps.sort_by(|a, b| a.libname.cmp(&b.libname));
+    Ok(deps)
+}
+
+// Picks the fingerprint of each locked dependency. When a crate was built
+// several times, such as after its source changed, the freshest build that
+// matches the locked version wins. Fingerprints read from the target
+// directory are unversioned, so they have to be compared too, otherwise the
+// first one found wins and a stale build may be linked.
+pub(crate) fn select_fingerprints<I>(
+    locked_deps: &HashMap<String, String>,
+    fingerprints: I,
+) -> HashMap<String, Fingerprint>
+where
+    I: IntoIterator<Item = Fingerprint>,
+{
+    let mut found_deps: HashMap<String, Fingerprint> = HashMap::new();
+    for finger in fingerprints {
         let locked_ver = match locked_deps.get(&finger.name()) {
             Some(ver) => ver,
             None => continue,
         };
-
-        // TODO this should be refactored to something more readable
-        match (found_deps.entry(finger.name()), finger.version()) {
-            (Entry::Occupied(mut e), Some(ver)) => {
-                // we find better match only if it is exact version match
-                // and has fresher build time
-                if *locked_ver == ver && e.get().mtime < finger.mtime {
-                    e.insert(finger);
-                }
+        let matches = finger
+            .version()
+            .as_ref()
+            .is_none_or(|ver| ver == locked_ver);
+        if !matches {
+            continue;
+        }
+        match found_deps.entry(finger.name()) {
+            Entry::Occupied(mut e) if e.get().mtime < finger.mtime => {
+                e.insert(finger);
             }
-            (Entry::Vacant(e), ver) => {
-                // we see an exact match or unversioned version
-                if ver.unwrap_or_else(|| locked_ver.clone()) == *locked_ver {
-                    e.insert(finger);
-                }
+            Entry::Vacant(e) => {
+                e.insert(finger);
             }
             _ => (),
         }
     }
-
-    Ok(found_deps
-        .into_iter()
-        .filter_map(|(_, val)| if val.rlib.exists() { Some(val) } else { None })
-        .collect())
+    found_deps
 }
 
 // An iterator over the root dependencies in a lockfile
 #[derive(Debug)]
 struct LockedDeps {
-    dependencies: Vec<String>,
+    dependencies: Vec<(String, String)>,
 }
 
 fn get_cargo_meta<P: AsRef<Path> + std::convert::AsRef<std::ffi::OsStr>>(
     path: P,
-) -> Result<cargo_metadata::Metadata> {
+) -> Result<cargo_metadata::Metadata, Error> {
     Ok(cargo_metadata::MetadataCommand::new()
         .manifest_path(&path)
         .exec()?)
 }
 
 impl LockedDeps {
-    fn from_path<P: AsRef<Path>>(path: P) -> Result<LockedDeps> {
+    fn from_path<P: AsRef<Path>>(path: P) -> Result<LockedDeps, Error> {
         let path = path.as_ref().join("Cargo.toml");
         let metadata = get_cargo_meta(path)?;
         let workspace_members = metadata.workspace_members;
+        // Package ids are opaque, so resolve names and versions through the
+        // package list instead of parsing them out of the id representation
+        let packages: HashMap<_, _> = metadata
+            .packages
+            .into_iter()
+            .map(|package| {
+                let name = package.name.replace('-', "_");
+                (package.id, (name, package.version.to_string()))
+            })
+            .collect();
         let deps = metadata
             .resolve
-            .ok_or("Missing dependency metadata")?
+            .ok_or_else(|| Error::Fingerprint(String::from("missing dependency metadata")))?
             .nodes
             .into_iter()
             .filter(|node| workspace_members.contains(&node.id))
@@ -206,7 +1022,7 @@ impl LockedDeps {
             .chain(workspace_members.clone());
 
         Ok(LockedDeps {
-            dependencies: deps.map(|node| node.repr).collect(),
+            dependencies: deps.filter_map(|id| packages.get(&id).cloned()).collect(),
         })
     }
 }
@@ -215,51 +1031,51 @@ impl Iterator for LockedDeps {
     type Item = (String, String);
 
     fn next(&mut self) -> Option<(String, String)> {
-        let dep = self.dependencies.pop()?;
-        let mut parts = dep.split_whitespace();
-        let name = parts.next()?;
-        let val = parts.next()?;
-        Some((name.replace('-', "_"), val.to_owned()))
+        self.dependencies.pop()
     }
 }
 
 #[derive(Debug)]
-struct Fingerprint {
-    libname: String,
-    version: Option<String>, // version might not be present on path or vcs deps
-    rlib: PathBuf,
-    mtime: SystemTime,
+pub(crate) struct Fingerprint {
+    pub(crate) libname: String,
+    pub(crate) version: Option<String>, // version might not be present on path or vcs deps
+    pub(crate) rlib: PathBuf,
+    pub(crate) mtime: SystemTime,
 }
 
-fn guess_ext(mut path: PathBuf, exts: &[&str]) -> Result<PathBuf> {
+fn guess_ext(mut path: PathBuf, exts: &[&str]) -> Result<PathBuf, Error> {
     for ext in exts {
         path.set_extension(ext);
         if path.exists() {
             return Ok(path);
         }
     }
-    Err(ErrorKind::Fingerprint.into())
+    Err(Error::Fingerprint(format!(
+        "no library found for {}",
+        path.display()
+    )))
 }
 
 impl Fingerprint {
-    fn from_path<P: AsRef<Path>>(path: P) -> Result<Fingerprint> {
+    fn from_path<P: AsRef<Path>>(path: P) -> Result<Fingerprint, Error> {
         let path = path.as_ref();
+        let invalid = || Error::Fingerprint(format!("invalid fingerprint {}", path.display()));
 
         // Use the parent path to get libname and hash, replacing - with _
         let mut captures = path
             .parent()
             .and_then(Path::file_stem)
             .and_then(OsStr::to_str)
-            .ok_or(ErrorKind::Fingerprint)?
+            .ok_or_else(invalid)?
             .rsplit('-');
-        let hash = captures.next().ok_or(ErrorKind::Fingerprint)?;
+        let hash = captures.next().ok_or_else(invalid)?;
         let mut libname_parts = captures.collect::<Vec<_>>();
         libname_parts.reverse();
         let libname = libname_parts.join("_");
 
         path.extension()
             .and_then(|e| if e == "json" { Some(e) } else { None })
-            .ok_or(ErrorKind::Fingerprint)?;
+            .ok_or_else(invalid)?;
 
         let mut rlib = PathBuf::from(path);
         rlib.pop();
@@ -287,25 +1103,20 @@ impl Fingerprint {
     }
 }
 
-error_chain! {
-    errors { Fingerprint }
-    foreign_links {
-        Io(std::io::Error);
-        Metadata(cargo_metadata::Error);
-    }
-}
-
 #[derive(Clone, Copy)]
-enum CompileType {
+enum CompileType<'a> {
     Full,
     Check,
+    Fail(&'a [&'a str]),
 }
 
-fn edition_str(edition: &Edition) -> Option<&'static str> {
-    Some(match edition {
-        Edition::E2015 => "2015",
-        Edition::E2018 => "2018",
-        Edition::E2021 => "2021",
-        _ => return None,
-    })
+// The `--emit` argument that only checks an example, writing its outputs
+// next to `path`. Built as an `OsString`, as the path needn't be UTF-8.
+fn emit_metadata(path: &Path) -> OsString {
+    let mut arg = OsString::from("--emit=dep-info=");
+    arg.push(path);
+    arg.push(".d,metadata=");
+    arg.push(path);
+    arg.push(".m");
+    arg
 }
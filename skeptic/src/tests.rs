@@ -34,15 +34,86 @@ fn test_omitted_lines() {
 
 #[test]
 fn test_markdown_files_of_directory() {
-    let files = vec![
+    let files = [
+        "../testing/tests/batch-test.md",
+        "../testing/tests/compile-fail-test.md",
+        "../testing/tests/detected-legacy-template-test.md",
+        "../testing/tests/detected-legacy-template-test.md.skt.md",
+        "../testing/tests/edition-test.md",
+        "../testing/tests/exit-code-test.md",
+        "../testing/tests/expected-output-test.md",
         "../testing/tests/hashtag-test.md",
+        "../testing/tests/implicit-rust-test.md",
+        "../testing/tests/legacy-template-test.md",
+        "../testing/tests/legacy-template-test.md.skt.md",
+        "../testing/tests/raw-string-test.md",
+        "../testing/tests/run-input-test.md",
         "../testing/tests/section-names.md",
         "../testing/tests/should-panic-test.md",
+        "../testing/tests/skeptic.md",
+        "../testing/tests/template-inheritance-test.md",
+        "../testing/tests/template-inheritance-test.md.skt.md",
+        "../testing/tests/template-library-test.md",
+        "../testing/tests/template-library-test.md.skt.md",
+        "../testing/tests/templates/common.md",
+        "../testing/tests/timeout-test.md",
+        "../testing/tests/wrap-main-test.md",
     ];
     let files: Vec<PathBuf> = files.iter().map(PathBuf::from).collect();
     assert_eq!(markdown_files_of_directory("../testing/tests/"), files);
 }
 
+#[test]
+fn markdown_files_skip_ignored_and_excluded_files() {
+    let dir = tempfile::tempdir().unwrap();
+    for file in &[
+        "README.md",
+        "notes.txt",
+        "docs/guide.md",
+        "docs/deep/more.md",
+        "target/doc.md",
+        "vendor/lib/README.md",
+    ] {
+        let path = dir.path().join(file);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, "").unwrap();
+    }
+    std::fs::write(dir.path().join(".gitignore"), "/target\n").unwrap();
+
+    let relative = |files: Vec<PathBuf>| {
+        files
+            .iter()
+            .map(|file| file.strip_prefix(dir.path()).unwrap().to_owned())
+            .collect::<Vec<_>>()
+    };
+    let expected = |files: &[&str]| files.iter().map(PathBuf::from).collect::<Vec<_>>();
+
+    let files = MarkdownFiles::new(dir.path())
+        .exclude("vendor/**")
+        .find()
+        .unwrap();
+    assert_eq!(
+        relative(files),
+        expected(&["README.md", "docs/deep/more.md", "docs/guide.md"])
+    );
+
+    let files = MarkdownFiles::new(dir.path())
+        .include("docs/*.md")
+        .include("*.md")
+        .find()
+        .unwrap();
+    assert_eq!(relative(files), expected(&["README.md", "docs/guide.md"]));
+
+    let files = MarkdownFiles::new(dir.path())
+        .include("target/*.md")
+        .gitignore(false)
+        .find()
+        .unwrap();
+    assert_eq!(relative(files), expected(&["target/doc.md"]));
+
+    assert!(MarkdownFiles::new(dir.path()).include("[").find().is_err());
+}
+
 #[test]
 fn test_sanitization_of_testnames() {
     assert_eq!(sanitize_test_name("My_Fun"), "my_fun");
@@ -80,8 +151,11 @@ fn line_numbers_displayed_are_for_the_beginning_of_each_code_block() {
         ```"###,
     );
 
-    let tests =
-        extract_tests_from_string(&create_test_input(&get_lines(lines)), &String::from("blah"));
+    let tests = extract_tests_from_string(
+        &create_test_input(&get_lines(lines)),
+        &String::from("blah"),
+        false,
+    );
 
     let test_names: Vec<String> = tests
         .0
@@ -124,8 +198,11 @@ fn line_numbers_displayed_are_for_the_beginning_of_each_section() {
         ```"###,
     );
 
-    let tests =
-        extract_tests_from_string(&create_test_input(&get_lines(lines)), &String::from("blah"));
+    let tests = extract_tests_from_string(
+        &create_test_input(&get_lines(lines)),
+        &String::from("blah"),
+        false,
+    );
 
     let test_names: Vec<String> = tests
         .0
@@ -161,8 +238,11 @@ fn old_template_is_returned_for_old_skeptic_template_format() {
         }}
         "###,
     );
-    let tests =
-        extract_tests_from_string(&create_test_input(&get_lines(lines)), &String::from("blah"));
+    let tests = extract_tests_from_string(
+        &create_test_input(&get_lines(lines)),
+        &String::from("blah"),
+        false,
+    );
     assert_eq!(tests.1, Some(expected));
 }
 
@@ -178,16 +258,910 @@ fn old_template_is_not_returned_if_old_skeptic_template_is_not_specified() {
         ```
         "###,
     );
-    let tests =
-        extract_tests_from_string(&create_test_input(&get_lines(lines)), &String::from("blah"));
+    let tests = extract_tests_from_string(
+        &create_test_input(&get_lines(lines)),
+        &String::from("blah"),
+        false,
+    );
     assert_eq!(tests.1, None);
 }
 
+/// Writes `doc` to `doc.md` in a new temporary directory and returns the
+/// directory along with a builder for it, targeting a fixed triple.
+fn scaffold(doc: &str) -> (tempfile::TempDir, Skeptic) {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("doc.md"), doc).unwrap();
+    let mut skeptic = Skeptic::new();
+    skeptic
+        .doc("doc.md")
+        .root_dir(dir.path())
+        .out_dir(dir.path())
+        .target("x86_64-unknown-linux-gnu");
+    (dir, skeptic)
+}
+
+/// Generates the tests for `doc` and returns the contents of the test file.
+fn generate(doc: &str, configure: impl FnOnce(&mut Skeptic)) -> String {
+    let (dir, mut skeptic) = scaffold(doc);
+    configure(&mut skeptic);
+    skeptic.generate();
+    let out_file = skeptic
+        .out_file
+        .unwrap_or_else(|| PathBuf::from("skeptic-tests.rs"));
+    std::fs::read_to_string(dir.path().join(out_file)).unwrap()
+}
+
+#[test]
+fn builder_writes_tests_to_configured_out_file() {
+    let generated = generate("```rust\nfn main() {}\n```\n", |skeptic| {
+        skeptic.out_file("custom.rs");
+    });
+    assert!(generated.contains("fn doc_line_1()"));
+    assert!(generated.contains("x86_64-unknown-linux-gnu"));
+}
+
+#[test]
+fn unusual_paths_are_escaped_in_generated_tests() {
+    let (dir, mut skeptic) = scaffold("");
+    let root = dir.path().join("quote\"#dir");
+    std::fs::create_dir(&root).unwrap();
+    std::fs::write(root.join("doc.md"), "```rust\nfn main() {}\n```\n").unwrap();
+
+    skeptic.root_dir(&root).out_dir(&root).generate();
+
+    let generated = std::fs::read_to_string(root.join("skeptic-tests.rs")).unwrap();
+    assert!(generated.contains(&format!("{:?}", root.to_str().unwrap())));
+}
+
+#[cfg(unix)]
+#[test]
+fn non_utf8_paths_are_written_as_bytes() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = Path::new(OsStr::from_bytes(b"/tmp/caf\xe9"));
+    let expr = path_expr(path);
+    assert!(expr.starts_with("__skeptic::rt::path_from_bytes(&[47, "));
+    assert_eq!(
+        rt::path_from_bytes(path.as_os_str().as_bytes()),
+        path.to_owned()
+    );
+    assert_eq!(path_expr(Path::new("/tmp/caf\u{e9}")), "\"/tmp/caf\u{e9}\"");
+}
+
+#[test]
+fn manifest_describes_generated_tests() {
+    let (dir, mut skeptic) = scaffold(&unindent(
+        r###"
+        ```rust,skt-wrap,no_run
+        let a = 1;
+        let b = 2;
+        ```
+
+        ```rust,compile_fail,E0308
+        fn main() { let a: u8 = ""; }
+        ```
+        "###,
+    ));
+    std::fs::write(
+        dir.path().join("doc.md.skt.md"),
+        "```rust,skt-wrap\nfn main() {\n{{snippet}}\n}\n```\n",
+    )
+    .unwrap();
+
+    skeptic.out_file("doc-tests.rs").generate();
+
+    let manifest = std::fs::read_to_string(dir.path().join("doc-manifest.json")).unwrap();
+    let manifest: serde_json::Value = serde_json::from_str(&manifest).unwrap();
+    let tests = manifest["tests"].as_array().unwrap();
+    assert_eq!(tests.len(), 2);
+    assert_eq!(tests[0]["name"], "doc_line_1");
+    assert_eq!(tests[0]["file"], "doc.md");
+    assert_eq!(tests[0]["lines"]["start"], 2);
+    assert_eq!(tests[0]["lines"]["end"], 3);
+    assert_eq!(tests[0]["no_run"], true);
+    assert_eq!(tests[0]["template"], "skt-wrap");
+    assert_eq!(
+        tests[0]["source"],
+        "\nfn main() {\nlet a = 1;\nlet b = 2;\n\n}\n"
+    );
+    assert_eq!(tests[1]["compile_fail"], true);
+    assert_eq!(tests[1]["error_codes"][0], "E0308");
+    assert_eq!(tests[1]["template"], serde_json::Value::Null);
+}
+
+#[test]
+fn tests_are_grouped_in_modules_per_document_and_heading() {
+    let (dir, skeptic) = scaffold(&unindent(
+        r###"
+        # Guide
+
+        ## Setup
+
+        ### 2. Install
+
+        ```rust
+        fn main() {}
+        ```
+
+        ## Usage
+
+        ```rust
+        fn main() {}
+        ```
+        "###,
+    ));
+    skeptic.generate();
+
+    let tests = std::fs::read_to_string(dir.path().join("skeptic-tests.rs")).unwrap();
+    let modules = tests
+        .lines()
+        .filter(|line| line.trim_start().starts_with("mod ") || line.contains("fn doc_"))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        modules,
+        vec![
+            "mod doc {",
+            "    mod guide {",
+            "        mod setup {",
+            "            mod _2_install {",
+            "#[test] fn doc_sect_setup_line_7() {",
+            "        mod usage {",
+            "#[test] fn doc_sect_usage_line_13() {",
+        ]
+    );
+
+    let manifest = std::fs::read_to_string(dir.path().join("skeptic-manifest.json")).unwrap();
+    let manifest: serde_json::Value = serde_json::from_str(&manifest).unwrap();
+    assert_eq!(
+        manifest["tests"][0]["module"],
+        "doc::guide::setup::_2_install"
+    );
+}
+
+#[test]
+fn template_is_split_around_placeholder() {
+    let vars = TemplateVars {
+        name: "doc_line_3",
+        file: "docs/doc.md",
+        crate_name: Some("my_crate"),
+    };
+    let template = "use {{crate}};\nfn main() {{{ snippet }}}\n// {{name}} in {{file}}\n";
+    let (prefix, suffix) = split_template(template, &vars).unwrap();
+    assert_eq!(prefix, "use my_crate;\nfn main() {");
+    assert_eq!(suffix, "}\n// doc_line_3 in docs/doc.md\n");
+
+    let (prefix, _) = split_template("fn main() {{ let a = 1; }} {{snippet}}", &vars).unwrap();
+    assert_eq!(prefix, "fn main() {{ let a = 1; }} ");
+
+    let err = split_template("{{snippet}} {{nope}}", &vars).unwrap_err();
+    assert_eq!(err, "unknown placeholder `{{nope}}`");
+    assert!(split_template("fn main() {}", &vars).is_err());
+    assert!(split_template("{{snippet}}{{snippet}}", &vars).is_err());
+}
+
+#[test]
+fn crate_is_named_after_the_library_target() {
+    let dir = tempfile::tempdir().unwrap();
+    let manifest = "[package]\nname = \"my-package\"\nversion = \"0.1.0\"\n";
+    std::fs::write(dir.path().join("main.rs"), "fn main() {}\n").unwrap();
+    std::fs::write(dir.path().join("lib.rs"), "").unwrap();
+
+    std::fs::write(
+        dir.path().join("Cargo.toml"),
+        format!("{}[lib]\npath = \"lib.rs\"\n", manifest),
+    )
+    .unwrap();
+    assert_eq!(crate_name(dir.path()).as_deref(), Some("my_package"));
+
+    std::fs::write(
+        dir.path().join("Cargo.toml"),
+        format!("{}[lib]\nname = \"mylib\"\npath = \"lib.rs\"\n", manifest),
+    )
+    .unwrap();
+    assert_eq!(crate_name(dir.path()).as_deref(), Some("mylib"));
+
+    std::fs::write(
+        dir.path().join("Cargo.toml"),
+        format!("{}[[bin]]\nname = \"tool\"\npath = \"main.rs\"\n", manifest),
+    )
+    .unwrap();
+    assert_eq!(crate_name(dir.path()), None);
+}
+
+#[test]
+fn legacy_template_is_split_around_placeholder() {
+    let (prefix, suffix) = split_format_template("fn main() {{\n    {}\n}}\n").unwrap();
+    assert_eq!(prefix, "fn main() {\n    ");
+    assert_eq!(suffix, "\n}\n");
+
+    assert!(split_format_template("fn main() {\n    {}\n}}\n").is_err());
+    assert!(split_format_template("{} {}").is_err());
+    assert!(split_format_template("fn main() {{}}").is_err());
+}
+
+#[test]
+fn legacy_templates_are_detected() {
+    assert!(is_legacy_template("fn main() {{\n    {}\n}}\n"));
+    assert!(is_legacy_template("{{name}} {}"));
+    assert!(!is_legacy_template("fn main() {\n    {{snippet}}\n}\n"));
+    assert!(!is_legacy_template("fn main() {{ {{ snippet }} }}"));
+    assert!(!is_legacy_template("fn main() {\n    {}\n}\n"));
+}
+
+#[test]
+fn string_literals_cannot_be_ended_by_their_contents() {
+    assert_eq!(string_literal("plain"), "r#\"plain\"#");
+    assert_eq!(
+        string_literal("let s = r##\"a\"##;"),
+        "r###\"let s = r##\"a\"##;\"###"
+    );
+    assert_eq!(string_literal("a\r\nb\"#"), "\"a\\r\\nb\\\"#\"");
+}
+
+#[test]
+fn line_map_accounts_for_template_and_omitted_lines() {
+    let lines = get_lines(String::from("let a = 1;\n    # let b = 2;\nlet c = 3;"));
+    let (prefix_lines, offsets) = create_line_map("use std::fs;\nfn main() {\n    ", &lines);
+    assert_eq!(prefix_lines, 3);
+    assert_eq!(&offsets[..3], &[-4, 6, 0]);
+}
+
+#[test]
+fn diagnostics_are_remapped_to_markdown_locations() {
+    let mut snippet = rt::Snippet::new("root", "out", "target", "");
+    snippet.source("README.md", 40, 3, &[-4, 2, 0]);
+
+    let stderr = "error[E0308]: mismatched types\n --> /tmp/x/test.rs:5:9\n";
+    assert_eq!(
+        snippet.remap_diagnostics(stderr, "/tmp/x/test.rs", 0),
+        "error[E0308]: mismatched types\n --> README.md:41:11\n"
+    );
+
+    // Locations inside the template are left alone
+    let stderr = " --> /tmp/x/test.rs:2:1\n";
+    assert_eq!(
+        snippet.remap_diagnostics(stderr, "/tmp/x/test.rs", 0),
+        stderr
+    );
+}
+
+#[test]
+fn output_block_is_attached_to_preceding_test() {
+    let lines = unindent(
+        r###"
+        ```rust
+        fn main() {
+            println!("hello");
+        }
+        ```
+
+        Which prints:
+
+        ```text,skt-output
+        hello
+        ```
+
+        ```rust
+        fn main() {}
+        ```
+
+        ```text
+        not an output block
+        ```
+
+        ```skt-output
+        stray output
+        ```
+        "###,
+    );
+    let tests = extract_tests_from_string(&lines, "blah", false).0;
+    assert_eq!(tests[0].expected_output, Some(String::from("hello\n")));
+    assert_eq!(tests[1].expected_output, None);
+}
+
+#[test]
+fn run_inputs_are_read_from_info_string_and_stdin_block() {
+    let lines = unindent(
+        r###"
+        ```rust,args="-v input.txt",env=RUST_LOG=debug,env=EMPTY
+        fn main() {}
+        ```
+
+        ```text,skt-stdin
+        line
+        ```
+
+        ```text,skt-output
+        out
+        ```
+
+        ```rust
+        fn main() {}
+        ```
+
+        ```text
+        not a stdin block
+        ```
+
+        ```skt-stdin
+        stray input
+        ```
+        "###,
+    );
+    let tests = extract_tests_from_string(&lines, "blah", false).0;
+    assert_eq!(tests[0].args, vec!["-v", "input.txt"]);
+    assert_eq!(
+        tests[0].env,
+        vec![
+            (String::from("RUST_LOG"), String::from("debug")),
+            (String::from("EMPTY"), String::new()),
+        ]
+    );
+    assert_eq!(tests[0].stdin, Some(String::from("line\n")));
+    assert_eq!(tests[0].expected_output, Some(String::from("out\n")));
+    assert_eq!(tests[1].stdin, None);
+}
+
+#[test]
+fn timeout_info_string_is_in_seconds() {
+    let info = parse_code_block_info("rust,timeout=5");
+    assert_eq!(info.timeout, Some(std::time::Duration::from_secs(5)));
+    assert_eq!(parse_code_block_info("rust,timeout=soon").timeout, None);
+}
+
+#[test]
+fn malformed_attribute_values_are_reported() {
+    let info = parse_info("timeout=5s", true);
+    assert!(info.is_rust);
+    assert_eq!(
+        info.invalid.as_deref(),
+        Some("`timeout=5s` is not a number of seconds")
+    );
+    let info = parse_code_block_info("rust,exit_code=abc");
+    assert_eq!(info.exit_code, None);
+    assert_eq!(
+        info.invalid.as_deref(),
+        Some("`exit_code=abc` is not an exit code")
+    );
+
+    let (_dir, skeptic) = scaffold("Text\n\n```rust,timeout=5s\nfn main() {}\n```\n");
+    let err = skeptic.examples().unwrap_err();
+    match err {
+        Error::InfoString(ref msg) => {
+            assert!(msg.ends_with("doc.md:3: `timeout=5s` is not a number of seconds"))
+        }
+        ref err => panic!("unexpected error: {}", err),
+    }
+}
+
+#[test]
+fn output_diff_marks_missing_and_unexpected_lines() {
+    assert_eq!(rt::diff_lines("a\nb\nc", "a\nx\nc"), " a\n-b\n+x\n c\n");
+}
+
+#[test]
+fn compile_fail_info_string_collects_error_codes() {
+    let info = parse_code_block_info("rust,compile_fail,E0382,E0499");
+    assert!(info.is_rust);
+    assert!(info.compile_fail);
+    assert_eq!(info.error_codes, vec!["E0382", "E0499"]);
+
+    let info = parse_code_block_info("compile_fail");
+    assert!(info.compile_fail);
+    assert!(info.error_codes.is_empty());
+}
+
+#[test]
+fn edition_info_string_overrides_edition() {
+    let info = parse_code_block_info("rust,edition2018");
+    assert!(info.is_rust);
+    assert_eq!(info.edition.as_deref(), Some("2018"));
+
+    assert_eq!(parse_code_block_info("rust").edition, None);
+}
+
+#[test]
+fn batchable_tests_are_merged_into_one_crate() {
+    let (dir, mut skeptic) = scaffold(&unindent(
+        r###"
+        ```rust
+        fn main() {}
+        ```
+
+        ```rust,no_run
+        fn main() {}
+        ```
+
+        ```rust
+        fn main() {
+            println!("hi");
+        }
+        ```
+        "###,
+    ));
+    skeptic.batch(true).generate();
+
+    let batch = std::fs::read_to_string(dir.path().join("skeptic-batch-doc-0.rs")).unwrap();
+    assert!(batch.contains("mod doc_line_1 {\nfn main() {}\n"));
+    assert!(batch.contains("Ok(\"doc_line_9\") =>"));
+    assert!(!batch.contains("doc_line_5"));
+
+    let tests = std::fs::read_to_string(dir.path().join("skeptic-tests.rs")).unwrap();
+    assert!(tests.contains("\"doc_line_1\", 1)"));
+}
+
+#[test]
+fn missing_templates_are_reported_with_locations() {
+    let (dir, skeptic) = scaffold(&unindent(
+        r###"
+        ```rust,skt-nope
+        fn main() {}
+        ```
+
+        ```rust,skt-wrap
+        let a = 1;
+        ```
+
+        ```rust,skt-typo
+        fn main() {}
+        ```
+        "###,
+    ));
+    std::fs::write(
+        dir.path().join("doc.md.skt.md"),
+        "```rust,skt-wrap\nfn main() { {{snippet}} }\n```\n\n```rust,skt-other\n{{snippet}}\n```\n",
+    )
+    .unwrap();
+
+    let err = skeptic.try_generate().unwrap_err();
+    let missing = match err {
+        Error::MissingTemplates(ref missing) => missing,
+        ref err => panic!("unexpected error: {}", err),
+    };
+    let found = missing
+        .iter()
+        .map(|m| (m.template.as_str(), m.line))
+        .collect::<Vec<_>>();
+    assert_eq!(found, vec![("nope", 1), ("typo", 9)]);
+    assert_eq!(missing[0].available, vec!["other", "wrap"]);
+    assert!(err
+        .to_string()
+        .contains("doc.md:9: skt-typo is not defined, "));
+}
+
+#[test]
+fn library_templates_are_shared_and_overridden_by_documents() {
+    let lines = unindent(
+        r###"
+        ```rust,skt-wrap
+        let a = 1;
+        ```
+
+        ```rust,skt-shared
+        let b = 2;
+        ```
+
+        ```rust,skt-typo
+        let c = 3;
+        ```
+        "###,
+    );
+    let (dir, mut skeptic) = scaffold(&lines);
+    std::fs::write(
+        dir.path().join("doc.md.skt.md"),
+        "```rust,skt-wrap\nfn main() { {{snippet}} }\n```\n",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.path().join("skeptic-templates.md"),
+        "```rust,skt-wrap\n{{snippet}}\n```\n\n```rust,skt-shared\nfn main() { {{snippet}} }\n```\n",
+    )
+    .unwrap();
+
+    skeptic.doc("skeptic-templates.md");
+    let err = skeptic.try_generate().unwrap_err();
+    let msg = err.to_string();
+    assert!(msg.contains("doc.md:9: skt-typo is not defined, "));
+    assert!(msg.contains("skeptic-templates.md define skt-shared, skt-wrap"));
+
+    std::fs::write(dir.path().join("doc.md"), lines.replace("typo", "shared")).unwrap();
+    skeptic.try_generate().unwrap();
+    let manifest = std::fs::read_to_string(dir.path().join("skeptic-manifest.json")).unwrap();
+    let manifest: serde_json::Value = serde_json::from_str(&manifest).unwrap();
+    // The library itself isn't tested as a document
+    let files = manifest["tests"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|test| test["template_file"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        files,
+        vec![
+            "doc.md.skt.md",
+            "skeptic-templates.md",
+            "skeptic-templates.md"
+        ]
+    );
+}
+
+#[test]
+fn templates_extend_templates_and_fill_their_slots() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("templates.md");
+    let templates = unindent(
+        r###"
+        ```rust,skt-main
+        {{slot:prelude}}
+        fn main() {
+            {{snippet}}
+        }
+        ```
+
+        ```rust,skt-io,extends=skt-main,slot=prelude
+        use std::io;
+        {{slot:prelude}}
+        ```
+
+        ```rust,skt-fmt,extends=skt-io,slot=prelude
+        use std::fmt;
+        ```
+
+        ```rust,skt-fmt
+        let _ = {{snippet}};
+        ```
+        "###,
+    );
+    std::fs::write(&path, templates).unwrap();
+
+    let composed = compose_templates(&load_templates(&path).unwrap(), false).unwrap();
+    assert_eq!(
+        composed["io"].text,
+        "use std::io;\n{{slot:prelude}}\nfn main() {\n    {{snippet}}\n}\n"
+    );
+    assert_eq!(
+        composed["fmt"].text,
+        "use std::io;\nuse std::fmt;\nfn main() {\n    let _ = {{snippet}};\n}\n"
+    );
+    assert!(compose_templates(&load_templates(&path).unwrap(), true).is_err());
+}
+
+#[test]
+fn template_cycles_and_unknown_parents_are_reported() {
+    let dir = tempfile::tempdir().unwrap();
+    let compose = |templates: &str| {
+        let path = dir.path().join("templates.md");
+        std::fs::write(&path, templates).unwrap();
+        compose_templates(&load_templates(&path).unwrap(), false)
+            .unwrap_err()
+            .to_string()
+    };
+
+    let err = compose(
+        "```rust,skt-a,extends=skt-b\n{{snippet}}\n```\n\n```rust,skt-b,extends=skt-a\n{{snippet}}\n```\n",
+    );
+    assert!(err.ends_with("skt-a is extended in a cycle: skt-a extends skt-b, skt-b extends skt-a"));
+    let err = compose("```rust,skt-a,extends=skt-nope\n{{snippet}}\n```\n");
+    assert!(err.ends_with("skt-a extends skt-nope, which isn't defined"));
+    let err = compose(
+        "```rust,skt-a\n{{snippet}}\n```\n\n```rust,skt-b,extends=skt-a,slot=prelude\nuse std::io;\n```\n",
+    );
+    assert!(err.ends_with("skt-b skt-a has no `{{slot:prelude}}` placeholder"));
+    let err = compose(
+        "```rust,skt-a\nfn main() {{ {} }}\n```\n\n```rust,skt-b,extends=skt-a\nprintln!(\"b\");\n{{snippet}}\n```\n",
+    );
+    assert!(err.contains("templates.md: skt-b skt-a has no `{{snippet}}` placeholder"));
+}
+
+#[test]
+fn examples_are_wrapped_in_main_like_rustdoc() {
+    let lines = unindent(
+        r###"
+        ```rust
+        # #![allow(unused)]
+        use mycrate::Thing;
+        ```
+
+        ```rust
+        extern crate mycrate;
+        let n: u8 = "1".parse()?;
+        Ok(())
+        ```
+
+        ```rust
+        fn main() {}
+        ```
+        "###,
+    );
+    let mut tests = extract_tests_from_string(&lines, "doc", false).0;
+    for test in &mut tests {
+        wrap_in_main(test, Some("mycrate"));
+    }
+    let wrappers = tests
+        .iter()
+        .map(|test| test.main_wrapper.clone().unwrap())
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        wrappers[0].0,
+        "#![allow(unused)]\nextern crate mycrate;\nfn main() {\n"
+    );
+    assert_eq!(tests[0].text, vec!["\n", "use mycrate::Thing;\n"]);
+    assert_eq!(
+        wrappers[1],
+        (
+            String::from(
+                "extern crate mycrate;\nfn main() -> Result<(), Box<dyn std::error::Error>> {\n"
+            ),
+            String::from("}\n")
+        )
+    );
+    assert_eq!(wrappers[2], (String::new(), String::new()));
+}
+
+#[test]
+fn attributes_of_extern_crate_lines_are_hoisted_with_them() {
+    let lines = unindent(
+        r###"
+        ```rust
+        #[macro_use]
+        extern crate mycrate;
+        let n = answer!();
+        ```
+
+        ```rust
+        #[derive(Debug)]
+        struct Point;
+        ```
+        "###,
+    );
+    let mut tests = extract_tests_from_string(&lines, "doc", false).0;
+    for test in &mut tests {
+        wrap_in_main(test, Some("mycrate"));
+    }
+
+    assert_eq!(
+        tests[0].main_wrapper.as_ref().unwrap().0,
+        "#[macro_use]\nextern crate mycrate;\nfn main() {\n"
+    );
+    assert_eq!(tests[0].text, vec!["\n", "\n", "let n = answer!();\n"]);
+    assert_eq!(tests[1].main_wrapper.as_ref().unwrap().0, "fn main() {\n");
+    assert_eq!(tests[1].text, vec!["#[derive(Debug)]\n", "struct Point;\n"]);
+}
+
+#[test]
+fn examples_are_extracted_with_templates_applied() {
+    let (dir, skeptic) = scaffold(&unindent(
+        r###"
+        ```rust,ignore
+        fn main() {}
+        ```
+
+        ```rust,skt-wrap
+        let a = 1;
+        ```
+        "###,
+    ));
+    std::fs::write(
+        dir.path().join("doc.md.skt.md"),
+        "```rust,skt-wrap\nfn main() { {{snippet}} }\n```\n",
+    )
+    .unwrap();
+
+    let examples = skeptic.examples().unwrap();
+    let names = examples.iter().map(Example::name).collect::<Vec<_>>();
+    assert_eq!(names, vec!["doc_line_1", "doc_line_5"]);
+    assert!(examples[0].is_ignored());
+    assert!(!examples[1].is_ignored());
+    assert!(!dir.path().join("skeptic-tests.rs").exists());
+}
+
+#[test]
+fn unlabeled_code_blocks_are_rust_when_implicit() {
+    let lines = unindent(
+        r###"
+        ```rust
+        fn main() {}
+        ```
+
+            fn main() {}
+
+        ```
+        fn main() {}
+        ```
+
+        ```no_run
+        fn main() {}
+        ```
+
+        ```text
+        not rust
+        ```
+
+        ```skt-output
+        not rust either
+        ```
+        "###,
+    );
+    let lines = |implicit_rust| {
+        extract_tests_from_string(&lines, "blah", implicit_rust)
+            .0
+            .into_iter()
+            .map(|test| test.line)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(lines(false), vec![2]);
+    assert_eq!(lines(true), vec![2, 5, 8, 12]);
+}
+
+#[test]
+fn mdbook_includes_are_expanded() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(
+        dir.path().join("lib.rs"),
+        "use std::fs;\n// ANCHOR: body\nfn body() {}\n// ANCHOR_END: body\nfn main() {}\n",
+    )
+    .unwrap();
+
+    let text = "```rust\n{{#include lib.rs:body}}\n```\n\\{{#include lib.rs}}\n";
+    let expansion = mdbook::expand_includes(text, dir.path()).unwrap();
+    assert_eq!(
+        expansion.text,
+        "```rust\nfn body() {}\n```\n{{#include lib.rs}}\n"
+    );
+    assert_eq!(expansion.included, vec![dir.path().join("lib.rs")]);
+
+    let text = "{{#include lib.rs::1}}\n{{#rustdoc_include lib.rs:3:}}\nend\n";
+    let expansion = mdbook::expand_includes(text, dir.path()).unwrap();
+    assert_eq!(
+        expansion.text,
+        "use std::fs;\n# use std::fs;\n# // ANCHOR: body\nfn body() {}\n// ANCHOR_END: body\nfn main() {}\nend\n"
+    );
+    assert_eq!(expansion.lines, vec![0, 1, 1, 1, 1, 1, 2, 3]);
+
+    // Like mdBook, anchor lines are only left out when including an anchor
+    let expansion = mdbook::expand_includes("{{#include lib.rs}}\n", dir.path()).unwrap();
+    assert_eq!(
+        expansion.text,
+        "use std::fs;\n// ANCHOR: body\nfn body() {}\n// ANCHOR_END: body\nfn main() {}\n"
+    );
+
+    assert!(mdbook::expand_includes("{{#include lib.rs:nope}}", dir.path()).is_err());
+}
+
+#[test]
+fn mdbook_chapters_are_read_from_summary() {
+    let (dir, mut skeptic) = scaffold("");
+    let src = dir.path().join("book").join("text");
+    std::fs::create_dir_all(&src).unwrap();
+    std::fs::write(
+        dir.path().join("book").join("book.toml"),
+        "[book]\nsrc = \"text\"\n",
+    )
+    .unwrap();
+    let summary = unindent(
+        r###"
+        # Summary
+
+        [Intro](intro.md)
+
+        - [Chapter](chapter.md#start)
+            - [Draft]()
+        "###,
+    );
+    std::fs::write(src.join("SUMMARY.md"), summary).unwrap();
+    std::fs::write(src.join("intro.md"), "```rust\nfn main() {}\n```\n").unwrap();
+    std::fs::write(src.join("chapter.md"), "```rust\nfn main() {}\n```\n").unwrap();
+
+    let examples = skeptic.book("book").examples().unwrap();
+    let names = examples.iter().map(Example::name).collect::<Vec<_>>();
+    assert_eq!(names, vec!["intro_line_1", "chapter_line_1"]);
+}
+
+#[test]
+fn info_string_attributes_are_tokenized() {
+    let tokens = info_tokens(r#"rust,name=parse_config title="a, \"b\"" ignore"#);
+    assert_eq!(
+        tokens,
+        vec![
+            InfoToken::Word("rust"),
+            InfoToken::Attribute("name", String::from("parse_config")),
+            InfoToken::Attribute("title", String::from("a, \"b\"")),
+            InfoToken::Word("ignore"),
+        ]
+    );
+    assert_eq!(
+        parse_code_block_info("name=foo").name.as_deref(),
+        Some("foo")
+    );
+    assert!(parse_info("name=foo", true).is_rust);
+    assert!(!parse_info("title=x", true).is_rust);
+}
+
+#[test]
+fn no_run_should_panic_means_compile_fail() {
+    let info = parse_info("rust,no_run,should_panic", false);
+    assert!(info.compile_fail);
+    assert!(!info.should_panic);
+
+    let info = parse_info("rust,should_panic", false);
+    assert!(!info.compile_fail);
+    assert!(info.should_panic);
+}
+
+#[test]
+fn test_names_follow_info_string_and_naming_scheme() {
+    let (_dir, mut skeptic) = scaffold(&unindent(
+        r###"
+        ```rust,name=Parse-Config
+        fn main() {}
+        ```
+
+        # Usage
+
+        ```rust
+        fn main() {}
+        ```
+        "###,
+    ));
+
+    let examples = skeptic
+        .test_names(|parts| {
+            assert_eq!(parts.default_name(), "doc_sect_usage_line_7");
+            format!(
+                "{}_{}_{}",
+                parts.file_stem,
+                parts.section.as_ref().unwrap(),
+                parts.index
+            )
+        })
+        .examples()
+        .unwrap();
+    let names = examples.iter().map(Example::name).collect::<Vec<_>>();
+    assert_eq!(names, vec!["parse_config", "doc_usage_1"]);
+}
+
+#[test]
+fn duplicate_and_invalid_test_names_are_reported() {
+    // Each document's tests, and each section's, are in a module of their own
+    let (dir, mut skeptic) = scaffold(
+        "```rust,name=shared\nfn main() {}\n```\n\n# Two\n\n```rust,name=shared\nfn main() {}\n```\n",
+    );
+    std::fs::write(
+        dir.path().join("a.md"),
+        "```rust,name=shared\nfn main() {}\n```\n\n```rust,name=shared\nfn main() {}\n```\n",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.path().join("c.md"),
+        "```rust,name=loop\nfn main() {}\n```\n",
+    )
+    .unwrap();
+
+    assert_eq!(skeptic.examples().unwrap().len(), 2);
+    let err = skeptic.doc("a.md").examples().unwrap_err();
+    let duplicates = match err {
+        Error::DuplicateTestNames(ref duplicates) => duplicates,
+        ref err => panic!("unexpected error: {}", err),
+    };
+    assert_eq!(duplicates.len(), 1);
+    assert_eq!(duplicates[0].name, "shared");
+    assert_eq!(duplicates[0].locations.len(), 2);
+    assert!(err.to_string().contains("`shared` is used by "));
+
+    let err = skeptic.doc("c.md").examples().unwrap_err();
+    match err {
+        Error::InvalidTestNames(ref invalid) => assert_eq!(invalid[0].name, "loop"),
+        ref err => panic!("unexpected error: {}", err),
+    }
+}
+
 fn get_line_number_from_test_name(test: Test) -> String {
     String::from(
         test.name
             .split('_')
-            .last()
+            .next_back()
             .expect("There were no underscores!"),
     )
 }
@@ -198,3 +1172,47 @@ fn get_lines(lines: String) -> Vec<String> {
         .map(|string_slice| format!("{}\n", string_slice)) //restore line endings since they are removed by split.
         .collect()
 }
+
+// A crate built more than once, such as after its source changed, has a
+// fingerprint per build, none of which carries a version. The directory walk
+// may well return a stale one first, which used to be linked, failing
+// examples that use what the crate has since gained.
+#[test]
+fn stale_builds_of_a_dependency_are_not_linked() {
+    use std::time::{Duration, SystemTime};
+
+    let fingerprint =
+        |libname: &str, version: Option<&str>, rlib: &str, age: u64| rt::Fingerprint {
+            libname: libname.to_owned(),
+            version: version.map(str::to_owned),
+            rlib: PathBuf::from(rlib),
+            mtime: SystemTime::UNIX_EPOCH + Duration::from_secs(100 - age),
+        };
+    let mut locked = HashMap::new();
+    locked.insert(String::from("foo"), String::from("1.0.0"));
+
+    let found = rt::select_fingerprints(
+        &locked,
+        vec![
+            fingerprint("foo", None, "stale.rlib", 10),
+            fingerprint("foo", None, "fresh.rlib", 1),
+            fingerprint("foo", Some("0.9.0"), "other-version.rlib", 0),
+            fingerprint("foo", None, "older.rlib", 5),
+            fingerprint("bar", None, "unlocked.rlib", 0),
+        ],
+    );
+    assert_eq!(found.len(), 1);
+    assert_eq!(found["foo"].rlib, PathBuf::from("fresh.rlib"));
+}
+
+#[test]
+fn the_name_tests_refer_to_skeptic_by_is_reserved() {
+    assert!(is_test_name("skeptic"));
+    assert!(!is_test_name("__skeptic"));
+}
+
+#[test]
+fn builder_can_be_shared_between_threads() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Skeptic>();
+}
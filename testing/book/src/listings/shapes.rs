@@ -0,0 +1,11 @@
+// ANCHOR: area
+fn area(width: f64, height: f64) -> f64 {
+    width * height
+}
+// ANCHOR_END: area
+
+// ANCHOR: main
+fn main() {
+    assert_eq!(area(1.0, 4.0), 4.0);
+}
+// ANCHOR_END: main
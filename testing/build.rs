@@ -1,11 +1,51 @@
 extern crate skeptic;
 
 fn main() {
-    skeptic::generate_doc_tests(&[
-        "../README.md",
-        "../template-example.md",
-        "tests/hashtag-test.md",
-        "tests/should-panic-test.md",
-        "tests/section-names.md",
-    ]);
+    // For the tests calling into skeptic's runtime directly
+    println!(
+        "cargo:rustc-env=TARGET={}",
+        std::env::var("TARGET").unwrap()
+    );
+
+    skeptic::Skeptic::new()
+        .docs(&[
+            "../README.md",
+            "../template-example.md",
+            "tests/compile-fail-test.md",
+            "tests/detected-legacy-template-test.md",
+            "tests/edition-test.md",
+            "tests/exit-code-test.md",
+            "tests/expected-output-test.md",
+            "tests/hashtag-test.md",
+            "tests/raw-string-test.md",
+            "tests/run-input-test.md",
+            "tests/should-panic-test.md",
+            "tests/skeptic.md",
+            "tests/template-inheritance-test.md",
+            "tests/template-library-test.md",
+            "tests/timeout-test.md",
+            "tests/section-names.md",
+        ])
+        .implicit_rust_doc("tests/implicit-rust-test.md")
+        .book("book")
+        .templates("tests/templates")
+        .generate();
+
+    skeptic::Skeptic::new()
+        .doc("tests/batch-test.md")
+        .out_file("skeptic-batch-tests.rs")
+        .batch(true)
+        .generate();
+
+    skeptic::Skeptic::new()
+        .doc("tests/legacy-template-test.md")
+        .out_file("skeptic-legacy-tests.rs")
+        .legacy_templates(true)
+        .generate();
+
+    skeptic::Skeptic::new()
+        .doc("tests/wrap-main-test.md")
+        .out_file("skeptic-wrap-main-tests.rs")
+        .wrap_main(true)
+        .generate();
 }
@@ -0,0 +1,31 @@
+extern crate skeptic;
+
+use std::path::Path;
+
+use skeptic::rt::Snippet;
+
+include!(concat!(env!("OUT_DIR"), "/skeptic-batch-tests.rs"));
+
+// The example's own source prints something else, so the expected output
+// only comes out of the batch binary
+#[test]
+fn batched_examples_run_from_the_batch_binary() {
+    Snippet::new(
+        env!("CARGO_MANIFEST_DIR"),
+        env!("OUT_DIR"),
+        env!("TARGET"),
+        "fn main() { println!(\"not batched\"); }",
+    )
+    .source("tests/batch-test.md", 4, 1, &[0, 0, 0])
+    .name("batch_test_line_3")
+    .expected_output("first")
+    .batch(
+        concat!(env!("OUT_DIR"), "/skeptic-batch-batch_test-0.rs"),
+        "batch_test_line_3",
+        1,
+    )
+    .run();
+    assert!(Path::new(env!("OUT_DIR"))
+        .join("skeptic-batch/skeptic-batch-batch_test-0/out.exe")
+        .exists());
+}
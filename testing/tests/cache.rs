@@ -0,0 +1,40 @@
+//! Checks how builds of examples are cached, by calling skeptic's runtime
+//! directly.
+
+extern crate skeptic;
+
+use std::fs;
+use std::path::Path;
+
+use skeptic::rt::Snippet;
+
+fn cache_entries(name: &str) -> usize {
+    let cache_dir = Path::new(env!("OUT_DIR")).join("skeptic-cache");
+    fs::read_dir(cache_dir)
+        .unwrap()
+        .filter(|entry| {
+            let file_name = entry.as_ref().unwrap().file_name();
+            file_name
+                .to_string_lossy()
+                .starts_with(&format!("{}-", name))
+        })
+        .count()
+}
+
+#[test]
+fn edited_examples_replace_their_cached_build() {
+    let name = "cache_edited_example";
+    for greeting in &["hello", "goodbye", "hello"] {
+        Snippet::new(
+            env!("CARGO_MANIFEST_DIR"),
+            env!("OUT_DIR"),
+            env!("TARGET"),
+            &format!("fn main() {{ println!(\"{}\"); }}", greeting),
+        )
+        .source("cache.md", 1, 0, &[])
+        .name(name)
+        .expected_output(greeting)
+        .run();
+        assert_eq!(cache_entries(name), 1);
+    }
+}
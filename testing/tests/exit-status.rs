@@ -0,0 +1,64 @@
+//! Examples that are expected to fail their tests, checked by calling
+//! skeptic's runtime directly.
+
+extern crate skeptic;
+
+use std::time::Duration;
+
+use skeptic::rt::Snippet;
+
+fn snippet(text: &str) -> Snippet {
+    Snippet::new(
+        env!("CARGO_MANIFEST_DIR"),
+        env!("OUT_DIR"),
+        env!("TARGET"),
+        text,
+    )
+}
+
+fn run_error(snippet: &Snippet) -> skeptic::Error {
+    snippet.try_run().unwrap_err()
+}
+
+#[test]
+fn should_panic_fails_on_other_exit_codes() {
+    let err = run_error(snippet("fn main() { std::process::exit(2); }").should_panic());
+    assert_eq!(
+        err.to_string(),
+        "Example was expected to panic, but exited with code 2"
+    );
+}
+
+#[test]
+fn should_panic_fails_on_success() {
+    let err = run_error(snippet("fn main() {}").should_panic());
+    assert_eq!(
+        err.to_string(),
+        "Example succeeded but was expected to panic"
+    );
+}
+
+#[test]
+fn should_panic_fails_on_compile_errors() {
+    let err = run_error(snippet("fn main() { let _: u8 = \"\"; }").should_panic());
+    match err {
+        skeptic::Error::Compile(_) => {}
+        err => panic!("unexpected error: {}", err),
+    }
+}
+
+#[test]
+fn exit_code_must_match() {
+    let err = run_error(snippet("fn main() { std::process::exit(1); }").exit_code(2));
+    assert_eq!(
+        err.to_string(),
+        "Example was expected to exit with code 2, but exited with code 1"
+    );
+}
+
+#[test]
+fn examples_running_too_long_are_killed() {
+    let mut looping = snippet("fn main() { loop { std::thread::yield_now(); } }");
+    let err = run_error(looping.timeout(Duration::from_millis(500)));
+    assert_eq!(err.to_string(), "Example timed out after 0.5s");
+}